@@ -1,4 +1,4 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: Mat4 = Mat4::from_cols_array(&[
@@ -80,6 +80,25 @@ impl Camera {
         OPENGL_TO_WGPU_MATRIX * proj * Mat4::look_at_rh(self.position, self.position + self.view_direction, self.up)
     }
 
+    /// Position in pixels of a world point on a screen of `size` pixels, None when it is behind the camera
+    pub fn project(&self, point: Vec3, size: Vec2) -> Option<Vec2> {
+        let clip = self.get_view_matrix() * point.extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+        Some(Vec2::new((ndc.x + 1.0) * 0.5 * size.x, (1.0 - ndc.y) * 0.5 * size.y))
+    }
+
+    /// Origin and direction of the ray going through a pixel of a screen of `size` pixels
+    pub fn screen_ray(&self, pixel: Vec2, size: Vec2) -> (Vec3, Vec3) {
+        let ndc = Vec2::new(pixel.x / size.x * 2.0 - 1.0, 1.0 - pixel.y / size.y * 2.0);
+        let inverse = self.get_view_matrix().inverse();
+        let near = inverse.project_point3(ndc.extend(0.0));
+        let far = inverse.project_point3(ndc.extend(1.0));
+        (near, (far - near).normalize())
+    }
+
     const MOVE_SPEED: f32 = 0.1;
 
     pub fn move_update(&mut self) {
@@ -0,0 +1,23 @@
+// Helpers shared by the shaders, prepended to them when the modules are built
+
+// Inverse transpose of `m` up to a positive scale, the normals stay perpendicular to the surface under non-uniform
+// scale. The columns of the cofactor matrix are the cross products of the columns of `m`.
+fn inverse_transpose(m: mat3x3<f32>) -> mat3x3<f32> {
+    let cofactor = mat3x3<f32>(cross(m[1], m[2]), cross(m[2], m[0]), cross(m[0], m[1]));
+    let determinant = dot(m[0], cofactor[0]);
+    // A joint scaled to zero has no inverse, the normals are transformed like the positions
+    if determinant == 0.0 {
+        return m;
+    }
+    // Mirroring transforms flip the cofactor matrix
+    return cofactor * sign(determinant);
+}
+
+// Unit vector along `v`, or zero when it has no length, as `Vec3::normalize_or_zero` on the CPU
+fn normalize_or_zero(v: vec3<f32>) -> vec3<f32> {
+    let length_squared = dot(v, v);
+    if length_squared > 0.0 {
+        return v * inverseSqrt(length_squared);
+    }
+    return vec3<f32>(0.0);
+}
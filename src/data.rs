@@ -1,8 +1,54 @@
 use crate::basic_object::renderer::BasicObjectInstance;
 use crate::camera::Camera;
+use crate::ground::{Ground, GroundHit};
 use crate::hermite_spline::hermite_spline;
+use crate::model::{AdditiveLayer, Animator, BoneMask, IkChain, IkSettings, IkSolver, InfluenceReport, LookAt, Modelv2, Pose, RootMotionMode};
+use crate::playback::{CrossFade, Playhead, Sweeps, WrapMode};
 use egui_winit::winit::dpi::PhysicalPosition;
 use glam::{EulerRot, Mat4, Quat, Vec3};
+use std::collections::VecDeque;
+
+/// Maximum number of events kept in the queue, the oldest ones are dropped first
+const MAX_QUEUED_EVENTS: usize = 32;
+
+/// Animation event crossed by the playback
+#[derive(Clone, Debug)]
+pub struct FiredEvent {
+    pub animation: usize,
+    pub name: String,
+    pub time: f32,
+}
+
+/// Point of an IK chain moved with a gizmo
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IkHandle {
+    Target,
+    Pole,
+}
+
+/// Where the vertices are skinned
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SkinningMode {
+    /// In the vertex shader of every draw
+    #[default]
+    VertexShader,
+    /// Once per frame in a compute pass, drawn as static geometry
+    Compute,
+    /// Once per frame on the CPU, uploaded and drawn as static geometry
+    Cpu,
+}
+
+impl SkinningMode {
+    pub const ALL: [SkinningMode; 3] = [SkinningMode::VertexShader, SkinningMode::Compute, SkinningMode::Cpu];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SkinningMode::VertexShader => "Vertex shader",
+            SkinningMode::Compute => "Compute",
+            SkinningMode::Cpu => "CPU",
+        }
+    }
+}
 
 pub struct UserDomain {
     pub mouse_locked: bool,
@@ -17,10 +63,13 @@ pub struct UserDomain {
     pub draw_model_coordinates: bool,
 
     pub double_quat_joints_render: bool,
+    pub skinning: SkinningMode,
+    /// Joint influences dropped when the model was loaded
+    pub influence_report: InfluenceReport,
 
     pub speed: f32,
     pub pause: bool,
-    pub interpolation: f32,
+    pub playhead: Playhead,
     pub start_rotation: Vec3,
     pub end_rotation: Vec3,
 
@@ -39,6 +88,67 @@ pub struct UserDomain {
     pub selected_animation: usize,
     pub animations: Vec<String>,
     pub animations_duration: Vec<f32>,
+    /// Name and time of the events of each animation
+    pub animation_events: Vec<Vec<(String, f32)>>,
+    /// Events fired by the playback, in order, for the application to consume
+    pub event_queue: VecDeque<FiredEvent>,
+    /// Wrap mode of each animation
+    pub wrap_modes: Vec<WrapMode>,
+    /// Root motion mode of each animation
+    pub root_motion_modes: Vec<RootMotionMode>,
+    /// Set while the animation driving the model applies its root motion, the model follows `root_position` and
+    /// `root_yaw` instead of the spline
+    pub root_motion_active: bool,
+    /// Offset from `start_pos` accumulated by the root motion
+    pub root_position: Vec3,
+    pub root_yaw: f32,
+
+    /// Animation driven by `playhead`, it follows `selected_animation` through a cross-fade
+    pub playing_animation: usize,
+    pub cross_fade: Option<CrossFade>,
+    pub cross_fade_duration: f32,
+
+    /// Second animation blended over the playing one with `blend_weight`
+    pub blend_animation: Option<usize>,
+    pub blend_playhead: Playhead,
+    pub blend_weight: f32,
+    pub blend_mask: Option<usize>,
+
+    /// Animations added on top of the blended pose, in order
+    pub layers: Vec<AdditiveLayer>,
+
+    pub node_names: Vec<String>,
+    pub node_parents: Vec<Option<usize>>,
+    pub masks: Vec<BoneMask>,
+    /// Mask shown in the hierarchy view
+    pub edited_mask: Option<usize>,
+
+    /// Chains moved by inverse kinematics after the animation, in order
+    pub ik_chains: Vec<IkChain>,
+    pub ik_settings: IkSettings,
+    /// Chain and point whose gizmo is dragged with the mouse
+    pub dragged_ik_handle: Option<(usize, IkHandle)>,
+    /// Draw the bones of the last FABRIK solve
+    pub draw_ik_debug: bool,
+    /// Bones of the last FABRIK solve in world space
+    pub ik_debug_bones: Vec<(Vec3, Vec3)>,
+
+    /// Look-at constraints applied after the animation, before the IK chains
+    pub look_ats: Vec<LookAt>,
+
+    /// Plant the feet on `ground` after the animation
+    pub foot_ik: bool,
+    pub ground: Ground,
+    pub draw_ground: bool,
+    /// Upper leg, lower leg and foot of each leg planted by the foot IK
+    pub foot_ik_legs: Vec<[usize; 3]>,
+    /// Contacts of the feet with the ground found by the last foot IK
+    pub foot_contacts: Vec<GroundHit>,
+
+    /// State machine loaded from the `.states.json` file next to the model
+    pub animator: Option<Animator>,
+    /// Drive the animation from the state machine instead of the selected animation
+    pub use_state_machine: bool,
 }
 
 impl UserDomain {
@@ -52,10 +162,12 @@ impl UserDomain {
             lines: Vec::new(),
 
             double_quat_joints_render: false,
+            skinning: SkinningMode::default(),
+            influence_report: InfluenceReport::default(),
 
             speed: 0.45,
             pause: false,
-            interpolation: 0.0,
+            playhead: Playhead::new(),
 
             draw_world_coordinates: true,
             draw_model_coordinates: true,
@@ -76,11 +188,58 @@ impl UserDomain {
             selected_animation: 0,
             animations: vec!["Default".to_string()],
             animations_duration: vec![1.0],
+            animation_events: vec![Vec::new()],
+            event_queue: VecDeque::new(),
+            wrap_modes: vec![WrapMode::default()],
+            root_motion_modes: vec![RootMotionMode::default()],
+            root_motion_active: false,
+            root_position: Vec3::ZERO,
+            root_yaw: 0.0,
+
+            playing_animation: 0,
+            cross_fade: None,
+            cross_fade_duration: 0.3,
+
+            blend_animation: None,
+            blend_playhead: Playhead::new(),
+            blend_weight: 0.5,
+            blend_mask: None,
+
+            layers: Vec::new(),
+
+            node_names: Vec::new(),
+            node_parents: Vec::new(),
+            masks: Vec::new(),
+            edited_mask: None,
+
+            ik_chains: Vec::new(),
+            ik_settings: IkSettings::default(),
+            dragged_ik_handle: None,
+            draw_ik_debug: true,
+            ik_debug_bones: Vec::new(),
+
+            look_ats: Vec::new(),
+
+            foot_ik: false,
+            ground: Ground::plane(Vec3::new(-4.0, 1.0, -2.0)),
+            draw_ground: true,
+            foot_ik_legs: Vec::new(),
+            foot_contacts: Vec::new(),
+
+            animator: None,
+            use_state_machine: false,
         }
     }
 
     pub fn reset_animation(&mut self) {
-        self.interpolation = 0.0;
+        self.playhead.reset();
+        self.blend_playhead.reset();
+        for layer in self.layers.iter_mut() {
+            layer.playhead.reset();
+        }
+        self.cross_fade = None;
+        self.root_position = Vec3::ZERO;
+        self.root_yaw = 0.0;
         self.draw_world_coordinates = true;
         self.draw_model_coordinates = true;
         self.start_rotation = Vec3::new(0.0, 0.0, 0.0);
@@ -97,6 +256,28 @@ impl UserDomain {
         self.light_color = Vec3::new(0.5, 0.5, 0.5);
     }
 
+    pub fn push_event(&mut self, event: FiredEvent) {
+        if self.event_queue.len() == MAX_QUEUED_EVENTS {
+            self.event_queue.pop_front();
+        }
+        self.event_queue.push_back(event);
+    }
+
+    /// Remove a mask and update the indexes of the masks after it
+    pub fn remove_mask(&mut self, index: usize) {
+        self.masks.remove(index);
+        let update = |mask: Option<usize>| match mask {
+            Some(i) if i == index => None,
+            Some(i) if i > index => Some(i - 1),
+            mask => mask,
+        };
+        self.blend_mask = update(self.blend_mask);
+        self.edited_mask = update(self.edited_mask);
+        for layer in self.layers.iter_mut() {
+            layer.mask = update(layer.mask);
+        }
+    }
+
     pub(crate) fn save_mouse_pos(&mut self, pos: &PhysicalPosition<f64>) {
         self.mouse_pos = pos.clone();
     }
@@ -128,6 +309,25 @@ impl UserDomain {
             ));
         }
 
+        for chain in self.ik_chains.iter().filter(|chain| chain.enabled) {
+            arrow3d.push(Mat4::from_scale_rotation_translation(
+                Vec3::splat(0.25),
+                Quat::IDENTITY,
+                chain.target,
+            ));
+            if let Some(pole) = chain.pole.filter(|_| chain.solver == IkSolver::TwoBone) {
+                arrow3d.push(Mat4::from_scale_rotation_translation(Vec3::splat(0.1), Quat::IDENTITY, pole));
+            }
+        }
+
+        for look_at in self.look_ats.iter().filter(|look_at| look_at.enabled && !look_at.follow_camera) {
+            arrow3d.push(Mat4::from_scale_rotation_translation(
+                Vec3::splat(0.15),
+                Quat::IDENTITY,
+                look_at.target,
+            ));
+        }
+
         if self.arrow3d == arrow3d {
             false
         } else {
@@ -136,14 +336,81 @@ impl UserDomain {
         }
     }
 
-    pub fn calculate_model_matrix(&self) -> Mat4 {
-        let t = self.interpolation/self.animations_duration[self.selected_animation];
-        let start_rotaton = Quat::from_euler(
+    /// Move the model by a root motion translation, in model space, and yaw
+    pub fn move_root(&mut self, translation: Vec3, yaw: f32) {
+        self.root_position += self.root_rotation() * translation * self.scale;
+        self.root_yaw += yaw;
+    }
+
+    /// Move the model by the root motion of the animation over the intervals played, scaled by its weight in the pose.
+    /// Scrubbing the timeline does not move it.
+    pub fn apply_root_motion(&mut self, model: &Modelv2, animation: usize, sweeps: &Sweeps, weight: f32) {
+        if weight <= 0.0 {
+            return;
+        }
+        for (from, to) in &sweeps.played {
+            if let Some((translation, yaw)) = model.root_motion_delta(animation, *from, *to) {
+                self.move_root(translation * weight, yaw * weight);
+            }
+        }
+    }
+
+    /// Fade from the animation playing to the one picked in the GUI. A fade in progress goes on under the new one, so
+    /// the new fade starts from the pose it was showing.
+    pub fn play_selected_animation(&mut self) {
+        if self.selected_animation == self.playing_animation {
+            return;
+        }
+        let from_playhead = std::mem::take(&mut self.playhead);
+        let mut cross_fade = CrossFade::new(self.playing_animation, from_playhead, self.cross_fade_duration);
+        cross_fade.previous = self.cross_fade.take().map(Box::new);
+        self.cross_fade = Some(cross_fade);
+        self.playing_animation = self.selected_animation;
+    }
+
+    /// Pose of the animation picked in the GUI, blended with the ones it is fading from
+    pub fn sample_selected_animation(&self, model: &Modelv2) -> Pose {
+        let pose = model.sample_pose(self.playing_animation, self.playhead.time);
+        Self::blend_faded_out(model, pose, self.cross_fade.as_ref())
+    }
+
+    /// Blend `pose` over the pose faded out by `cross_fade`, itself blended with the fades it interrupted
+    fn blend_faded_out(model: &Modelv2, pose: Pose, cross_fade: Option<&CrossFade>) -> Pose {
+        match cross_fade {
+            Some(cross_fade) => {
+                let from_pose = model.sample_pose(cross_fade.from, cross_fade.from_playhead.time);
+                let mut from_pose = Self::blend_faded_out(model, from_pose, cross_fade.previous.as_deref());
+                from_pose.blend(&pose, cross_fade.weight());
+                from_pose
+            }
+            None => pose,
+        }
+    }
+
+    fn root_rotation(&self) -> Quat {
+        Quat::from_rotation_y(self.root_yaw) * self.start_quat()
+    }
+
+    fn start_quat(&self) -> Quat {
+        Quat::from_euler(
             EulerRot::ZYX,
             self.start_rotation.z.to_radians(),
             self.start_rotation.y.to_radians(),
             self.start_rotation.x.to_radians(),
-        );
+        )
+    }
+
+    pub fn calculate_model_matrix(&self) -> Mat4 {
+        if self.root_motion_active {
+            return Mat4::from_scale_rotation_translation(
+                Vec3::new(self.scale, self.scale, self.scale),
+                self.root_rotation(),
+                self.start_pos + self.root_position,
+            );
+        }
+
+        let t = self.playhead.time / self.animations_duration[self.selected_animation];
+        let start_rotaton = self.start_quat();
         let end_rotaton = Quat::from_euler(
             EulerRot::ZYX,
             self.end_rotation.z.to_radians(),
@@ -220,6 +487,41 @@ impl UserDomain {
             });
         }
 
+        if self.draw_ik_debug {
+            for (from, to) in self.ik_debug_bones.iter() {
+                lines.push(BasicObjectInstance {
+                    model: Self::create_line_mat_instance(*from, *to),
+                    color: glam::Vec4::new(1.0, 0.5, 0.0, 1.0),
+                });
+            }
+        }
+
+        if self.draw_ground {
+            let center = self.calculate_model_matrix().w_axis.truncate();
+            for (from, to) in self.ground.lines(center) {
+                lines.push(BasicObjectInstance {
+                    model: Self::create_line_mat_instance(from, to),
+                    color: glam::Vec4::new(0.5, 0.5, 0.5, 1.0),
+                });
+            }
+        }
+        if self.foot_ik {
+            for contact in self.foot_contacts.iter() {
+                let p = contact.position;
+                let contact_lines = [
+                    (p, p + contact.normal * 0.2),
+                    (p - Vec3::X * 0.05, p + Vec3::X * 0.05),
+                    (p - Vec3::Z * 0.05, p + Vec3::Z * 0.05),
+                ];
+                for (from, to) in contact_lines {
+                    lines.push(BasicObjectInstance {
+                        model: Self::create_line_mat_instance(from, to),
+                        color: glam::Vec4::new(0.0, 1.0, 0.0, 1.0),
+                    });
+                }
+            }
+        }
+
         if self.lines == lines {
             false
         } else {
@@ -235,14 +537,64 @@ impl UserDomain {
     pub fn create_line_mat_instance(from: Vec3, to: Vec3) -> Mat4 {
         let dir = to - from;
         let len = dir.length();
-        let dir = dir.normalize();
         let up = Vec3::NEG_Y;
-        let axis = dir.cross(up).normalize();
-        let angle = dir.dot(up).acos();
-        let rotation = Mat4::from_rotation_translation(Quat::from_axis_angle(axis, angle), Vec3::ZERO);
+        let dir = dir.normalize_or(up);
+        let rotation = Mat4::from_quat(Quat::from_rotation_arc(dir, up));
         let scale = Mat4::from_scale(Vec3::new(1.0, len, 1.0));
         let translation = Mat4::from_translation(from);
         let flip_y = Mat4::from_scale(Vec3::new(1.0, -1.0, 1.0));
         translation * flip_y * rotation * scale
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_scrubbing_does_not_move_root() {
+        let mut model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let sitting = model.animations().iter().position(|a| a.name == "Sitting").unwrap();
+        let duration = model.animations()[sitting].duration();
+        model.set_root_motion_mode(sitting, RootMotionMode::Apply);
+        let mut data = UserDomain::new();
+        let mut playhead = Playhead::new();
+        let sweeps = playhead.advance(0.1, duration, WrapMode::Once);
+        data.apply_root_motion(&model, sitting, &sweeps, 1.0);
+        let (position, yaw) = (data.root_position, data.root_yaw);
+        assert!(position != Vec3::ZERO);
+
+        // Dragged to the end while playing, the next advance only moves by its delta
+        playhead.time = duration;
+        let sweeps = playhead.advance(0.0, duration, WrapMode::Once);
+        assert_eq!(sweeps.scrubbed, Some((0.1, duration)));
+        data.apply_root_motion(&model, sitting, &sweeps, 1.0);
+        assert_eq!(data.root_position, position);
+        assert_eq!(data.root_yaw, yaw);
+    }
+
+    #[test]
+    fn test_selection_changed_during_cross_fade() {
+        let model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let mut data = UserDomain::new();
+        data.playhead.seek(0.2);
+        data.selected_animation = 1;
+        data.play_selected_animation();
+        data.playhead.seek(0.1);
+        data.cross_fade.as_mut().unwrap().update(data.cross_fade_duration / 2.0);
+        let blended = data.sample_selected_animation(&model);
+
+        // The new fade starts from the blend shown, not from the animation the old fade was leaving
+        data.selected_animation = 2;
+        data.play_selected_animation();
+        let cross_fade = data.cross_fade.as_ref().unwrap();
+        assert_eq!(cross_fade.from, 1);
+        assert_eq!(cross_fade.previous.as_ref().unwrap().from, 0);
+        let pose = data.sample_selected_animation(&model);
+        for (node, expected) in pose.nodes.iter().zip(&blended.nodes) {
+            assert!(node.translate.abs_diff_eq(expected.translate, 1e-4));
+            assert!(node.rotate.abs_diff_eq(expected.rotate, 1e-4));
+        }
+    }
+}
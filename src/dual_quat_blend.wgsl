@@ -0,0 +1,73 @@
+// Dual quaternion skinning, prepended to the shaders defining `dualQuatJoint` and `scaleJoint` after the influences
+// of their layout
+
+// Sum of the weighted dual quaternions, on the same side as the first joint `first`
+fn blendDualQuats(affected_joints: vec4<u32>, weights: vec4<f32>, first: vec4<f32>) -> mat2x4<f32> {
+    let dq0:mat2x4<f32> = dualQuatJoint(affected_joints.x);
+    let dq1:mat2x4<f32> = dualQuatJoint(affected_joints.y);
+    let dq2:mat2x4<f32> = dualQuatJoint(affected_joints.z);
+    let dq3:mat2x4<f32> = dualQuatJoint(affected_joints.w);
+
+    let wx = weights.x * sign(dot(first, dq0[0]));
+    let wy = weights.y * sign(dot(first, dq1[0]));
+    let wz = weights.z * sign(dot(first, dq2[0]));
+    let ww = weights.w * sign(dot(first, dq3[0]));
+
+    return wx * dq0 + wy * dq1 + wz * dq2 + ww * dq3;
+}
+
+// Rigid transform of a normalized dual quaternion
+fn dualQuatMat(bone: mat2x4<f32>) -> mat4x4<f32> {
+    let r = bone[0];
+    let t = bone[1];
+
+    return mat4x4<f32>(
+        1.0 - (2.0 * r.y * r.y) - (2.0 * r.z * r.z),
+            (2.0 * r.x * r.y) + (2.0 * r.w * r.z),
+            (2.0 * r.x * r.z) - (2.0 * r.w * r.y),
+        0.0,
+
+            (2.0 * r.x * r.y) - (2.0 * r.w * r.z),
+        1.0 - (2.0 * r.x * r.x) - (2.0 * r.z * r.z),
+            (2.0 * r.y * r.z) + (2.0 * r.w * r.x),
+        0.0,
+
+            (2.0 * r.x * r.z) + (2.0 * r.w * r.y),
+            (2.0 * r.y * r.z) - (2.0 * r.w * r.x),
+        1.0 - (2.0 * r.x * r.x) - (2.0 * r.y * r.y),
+        0.0,
+
+        2.0 * (-t.w * r.x + t.x * r.w - t.y * r.z + t.z * r.y),
+        2.0 * (-t.w * r.y + t.x * r.z + t.y * r.w - t.z * r.x),
+        2.0 * (-t.w * r.z - t.x * r.y + t.y * r.x + t.z * r.w),
+        1);
+}
+
+// Scales and shears are blended linearly, the rotations stay with the dual quaternions
+fn blendScales(affected_joints: vec4<u32>, weights: vec4<f32>) -> mat3x3<f32> {
+    return weights.x * scaleJoint(affected_joints.x) +
+        weights.y * scaleJoint(affected_joints.y) +
+        weights.z * scaleJoint(affected_joints.z) +
+        weights.w * scaleJoint(affected_joints.w);
+}
+
+// Skinning matrix of the blended joints
+fn dualQuatSkinMat(influences: Influences) -> mat4x4<f32> {
+    let first = dualQuatJoint(influenceJoints(influences, 0u).x)[0];
+    var blended = mat2x4<f32>();
+    var scale = mat3x3<f32>();
+    for (var influence_set = 0u; influence_set < INFLUENCE_SETS; influence_set++) {
+        let affected_joints = influenceJoints(influences, influence_set);
+        let weights = influenceWeights(influences, influence_set);
+        blended += blendDualQuats(affected_joints, weights, first);
+        scale += blendScales(affected_joints, weights);
+    }
+    let bone = blended * (1 / length(blended[0]));
+
+    let scaleMat = mat4x4<f32>(
+        vec4<f32>(scale[0], 0.0),
+        vec4<f32>(scale[1], 0.0),
+        vec4<f32>(scale[2], 0.0),
+        vec4<f32>(0.0, 0.0, 0.0, 1.0));
+    return dualQuatMat(bone) * scaleMat;
+}
@@ -0,0 +1,226 @@
+use glam::{Quat, Vec2, Vec3};
+
+/// Point hit on the ground
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GroundHit {
+    pub position: Vec3,
+    pub normal: Vec3,
+}
+
+/// Surface the feet are placed on
+#[derive(Clone, Debug)]
+pub enum Ground {
+    /// Infinite plane through `point`, tilted by `tilt` radians around the x and z axes
+    Plane {
+        point: Vec3,
+        tilt: Vec2,
+    },
+    Heightfield(Heightfield),
+}
+
+impl Ground {
+    pub fn plane(point: Vec3) -> Self {
+        Ground::Plane {
+            point,
+            tilt: Vec2::ZERO,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Ground::Plane { .. } => "Plane",
+            Ground::Heightfield(_) => "Heightfield",
+        }
+    }
+
+    /// First point of the ground straight below `origin`, within `max_distance`
+    pub fn cast_down(&self, origin: Vec3, max_distance: f32) -> Option<GroundHit> {
+        let hit = match self {
+            Ground::Plane { point, tilt } => {
+                let normal = plane_normal(*tilt);
+                // Moving down by `distance` reaches the plane
+                let distance = (origin - *point).dot(normal) / normal.y;
+                GroundHit {
+                    position: origin - Vec3::Y * distance,
+                    normal,
+                }
+            }
+            Ground::Heightfield(heightfield) => heightfield.hit(origin.x, origin.z)?,
+        };
+        let distance = origin.y - hit.position.y;
+        (0.0..=max_distance).contains(&distance).then_some(hit)
+    }
+
+    /// Segments drawing the ground around `center`
+    pub fn lines(&self, center: Vec3) -> Vec<(Vec3, Vec3)> {
+        match self {
+            Ground::Plane { point, tilt } => {
+                const HALF_SIZE: f32 = 4.0;
+                const STEPS: usize = 8;
+                let normal = plane_normal(*tilt);
+                let height =
+                    |x: f32, z: f32| point.y - ((x - point.x) * normal.x + (z - point.z) * normal.z) / normal.y;
+                let corner = Vec2::new(center.x, center.z) - Vec2::splat(HALF_SIZE);
+                let step = 2.0 * HALF_SIZE / STEPS as f32;
+                let at = |i: usize, j: usize| {
+                    let (x, z) = (corner.x + i as f32 * step, corner.y + j as f32 * step);
+                    Vec3::new(x, height(x, z), z)
+                };
+                (0..=STEPS)
+                    .flat_map(|i| [(at(i, 0), at(i, STEPS)), (at(0, i), at(STEPS, i))])
+                    .collect()
+            }
+            Ground::Heightfield(heightfield) => heightfield.lines(),
+        }
+    }
+}
+
+fn plane_normal(tilt: Vec2) -> Vec3 {
+    Quat::from_rotation_x(tilt.x) * Quat::from_rotation_z(tilt.y) * Vec3::Y
+}
+
+/// Grid of heights sampled every `cell_size` along x and z, interpolated in between
+#[derive(Clone, Debug)]
+pub struct Heightfield {
+    /// Corner of the grid with the lowest x and z, at the height 0.0 of the samples
+    pub origin: Vec3,
+    pub cell_size: f32,
+    /// Number of samples along x
+    pub columns: usize,
+    /// Number of samples along z
+    pub rows: usize,
+    /// Heights row after row
+    pub heights: Vec<f32>,
+}
+
+impl Heightfield {
+    /// Rolling hills of `amplitude` around `center` on a square of `size`
+    pub fn hills(center: Vec3, size: f32, resolution: usize, amplitude: f32) -> Self {
+        let resolution = resolution.max(2);
+        let cell_size = size / (resolution - 1) as f32;
+        let origin = center - Vec3::new(size / 2.0, 0.0, size / 2.0);
+        let heights = (0..resolution * resolution)
+            .map(|i| {
+                let x = (i % resolution) as f32 * cell_size;
+                let z = (i / resolution) as f32 * cell_size;
+                amplitude * (x * 1.3).sin() * (z * 0.9).cos()
+            })
+            .collect();
+        Self {
+            origin,
+            cell_size,
+            columns: resolution,
+            rows: resolution,
+            heights,
+        }
+    }
+
+    fn sample(&self, column: usize, row: usize) -> f32 {
+        self.heights[row * self.columns + column]
+    }
+
+    fn point(&self, column: usize, row: usize) -> Vec3 {
+        self.origin
+            + Vec3::new(
+                column as f32 * self.cell_size,
+                self.sample(column, row),
+                row as f32 * self.cell_size,
+            )
+    }
+
+    /// Ground at (x, z) on the two triangles of its cell, None outside of the grid
+    pub fn hit(&self, x: f32, z: f32) -> Option<GroundHit> {
+        let local = Vec2::new(x - self.origin.x, z - self.origin.z) / self.cell_size;
+        let max = Vec2::new((self.columns - 1) as f32, (self.rows - 1) as f32);
+        if local.x < 0.0 || local.y < 0.0 || local.x > max.x || local.y > max.y {
+            return None;
+        }
+        let cell = local.floor().min(max - Vec2::ONE);
+        let (column, row) = (cell.x as usize, cell.y as usize);
+        let (u, v) = (local.x - cell.x, local.y - cell.y);
+
+        // Split along the diagonal from (0, 0) to (1, 1) of the cell
+        let p00 = self.point(column, row);
+        let p11 = self.point(column + 1, row + 1);
+        let (corner, normal) = if u >= v {
+            let p10 = self.point(column + 1, row);
+            (p10, (p00 - p10).cross(p11 - p10))
+        } else {
+            let p01 = self.point(column, row + 1);
+            (p01, (p11 - p01).cross(p00 - p01))
+        };
+        let normal = normal.normalize();
+        let height = corner.y - ((x - corner.x) * normal.x + (z - corner.z) * normal.z) / normal.y;
+        Some(GroundHit {
+            position: Vec3::new(x, height, z),
+            normal,
+        })
+    }
+
+    /// Edges of the grid
+    pub fn lines(&self) -> Vec<(Vec3, Vec3)> {
+        let mut lines = Vec::new();
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                if column + 1 < self.columns {
+                    lines.push((self.point(column, row), self.point(column + 1, row)));
+                }
+                if row + 1 < self.rows {
+                    lines.push((self.point(column, row), self.point(column, row + 1)));
+                }
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plane() {
+        let ground = Ground::plane(Vec3::new(0.0, 1.0, 0.0));
+        let hit = ground.cast_down(Vec3::new(2.0, 3.0, -1.0), 5.0).unwrap();
+        assert_eq!(hit.position, Vec3::new(2.0, 1.0, -1.0));
+        assert_eq!(hit.normal, Vec3::Y);
+        // Too far or above
+        assert_eq!(ground.cast_down(Vec3::new(2.0, 3.0, -1.0), 1.0), None);
+        assert_eq!(ground.cast_down(Vec3::new(2.0, 0.5, -1.0), 5.0), None);
+
+        // Tilted around z the ground goes up along x
+        let ground = Ground::Plane {
+            point: Vec3::ZERO,
+            tilt: Vec2::new(0.0, std::f32::consts::FRAC_PI_4),
+        };
+        let hit = ground.cast_down(Vec3::new(1.0, 5.0, 0.0), 10.0).unwrap();
+        assert!(hit.position.abs_diff_eq(Vec3::new(1.0, 1.0, 0.0), 1e-5));
+        assert!(hit.normal.abs_diff_eq(Vec3::new(-1.0, 1.0, 0.0).normalize(), 1e-5));
+    }
+
+    #[test]
+    fn test_heightfield() {
+        // A single cell, raised at x = 1
+        let heightfield = Heightfield {
+            origin: Vec3::new(0.0, 1.0, 0.0),
+            cell_size: 2.0,
+            columns: 2,
+            rows: 2,
+            heights: vec![0.0, 1.0, 0.0, 1.0],
+        };
+        let hit = heightfield.hit(1.0, 0.5).unwrap();
+        assert!(hit.position.abs_diff_eq(Vec3::new(1.0, 1.5, 0.5), 1e-5));
+        assert!(hit.normal.abs_diff_eq(Vec3::new(-1.0, 2.0, 0.0).normalize(), 1e-5));
+        // The other triangle of the cell is on the same slope
+        assert!(heightfield
+            .hit(0.5, 1.5)
+            .unwrap()
+            .position
+            .abs_diff_eq(Vec3::new(0.5, 1.25, 1.5), 1e-5));
+        assert_eq!(heightfield.hit(3.0, 0.5), None);
+
+        let ground = Ground::Heightfield(heightfield);
+        assert!(ground.cast_down(Vec3::new(2.0, 5.0, 2.0), 10.0).is_some());
+        assert_eq!(ground.lines(Vec3::ZERO).len(), 4);
+    }
+}
@@ -1,5 +1,9 @@
-use crate::data::UserDomain;
-use egui::{Align2, ComboBox, Context, Slider};
+use crate::data::{SkinningMode, UserDomain};
+use crate::ground::{Ground, Heightfield};
+use crate::model::{AdditiveLayer, Animator, BoneMask, IkChain, IkSolver, LookAt, LookAtJoint, Motion, ParameterValue, RootMotionMode};
+use crate::playback::WrapMode;
+use egui::{pos2, Align2, CollapsingHeader, Color32, ComboBox, Context, DragValue, FontId, Response, RichText, Slider, Stroke, Ui};
+use glam::Vec3;
 
 pub fn gui(user_domain: &mut UserDomain, ui: &Context) {
     egui::Window::new("Infos")
@@ -78,6 +82,22 @@ pub fn gui(user_domain: &mut UserDomain, ui: &Context) {
 
             ui.collapsing("Animation", |ui| {
                 ui.checkbox(&mut user_domain.double_quat_joints_render, "Double Quat Joints");
+                ComboBox::from_label("Skinning").selected_text(user_domain.skinning.name()).show_ui(ui, |ui| {
+                    for mode in SkinningMode::ALL {
+                        ui.selectable_value(&mut user_domain.skinning, mode, mode.name());
+                    }
+                });
+                let report = &user_domain.influence_report;
+                ui.label(format!(
+                    "Joint influences up to {}, {} used",
+                    report.max_influences, report.used_influences
+                ));
+                if report.truncated_vertices > 0 {
+                    ui.label(format!(
+                        "{} of {} vertices truncated, dropped weight {:.3} mean {:.3} max",
+                        report.truncated_vertices, report.skinned_vertices, report.mean_dropped_weight(), report.max_dropped_weight
+                    ));
+                }
 
                 ComboBox::from_label("Animation")
                     .selected_text(format!("{:?}", user_domain.animations[user_domain.selected_animation]))
@@ -90,17 +110,293 @@ pub fn gui(user_domain: &mut UserDomain, ui: &Context) {
                     });
 
                 ui.add(
-                    Slider::new(&mut user_domain.speed, 0.0..=1.0)
+                    Slider::new(&mut user_domain.cross_fade_duration, 0.0..=2.0)
+                        .text("Cross-fade duration")
+                        .step_by(0.01),
+                );
+                if let Some(cross_fade) = &user_domain.cross_fade {
+                    ui.label(format!(
+                        "Fading from {} ({:.0}%)",
+                        user_domain.animations[cross_fade.from],
+                        cross_fade.weight() * 100.0
+                    ));
+                }
+
+                let blend_text = match user_domain.blend_animation {
+                    Some(i) => user_domain.animations[i].as_str(),
+                    None => "None",
+                };
+                ComboBox::from_label("Blend with").selected_text(blend_text).show_ui(ui, |ui| {
+                    ui.selectable_value(&mut user_domain.blend_animation, None, "None");
+                    for i in 0..user_domain.animations.len() {
+                        ui.selectable_value(&mut user_domain.blend_animation, Some(i), &user_domain.animations[i]);
+                    }
+                });
+                if user_domain.blend_animation.is_some() {
+                    ui.add(Slider::new(&mut user_domain.blend_weight, 0.0..=1.0).text("Blend weight"));
+                    mask_combo(ui, "Blend mask", &user_domain.masks, &mut user_domain.blend_mask);
+                }
+
+                let wrap_mode = &mut user_domain.wrap_modes[user_domain.selected_animation];
+                ComboBox::from_label("Wrap mode").selected_text(wrap_mode.name()).show_ui(ui, |ui| {
+                    for mode in WrapMode::ALL {
+                        ui.selectable_value(wrap_mode, mode, mode.name());
+                    }
+                });
+                let root_motion_mode = &mut user_domain.root_motion_modes[user_domain.selected_animation];
+                ComboBox::from_label("Root motion").selected_text(root_motion_mode.name()).show_ui(ui, |ui| {
+                    for mode in RootMotionMode::ALL {
+                        ui.selectable_value(root_motion_mode, mode, mode.name());
+                    }
+                });
+                if user_domain.root_motion_active && ui.button("Reset position").clicked() {
+                    user_domain.root_position = Vec3::ZERO;
+                    user_domain.root_yaw = 0.0;
+                }
+
+                ui.add(
+                    Slider::new(&mut user_domain.speed, -1.0..=1.0)
                         .text("Speed")
                         .step_by(0.01),
                 );
                 ui.checkbox(&mut user_domain.pause, "Pause");
-                ui.add(Slider::new(&mut user_domain.interpolation, 0.0..=user_domain.animations_duration[user_domain.selected_animation]).text("Interpolation"));
+                let duration = user_domain.animations_duration[user_domain.selected_animation];
+                let response = ui.add(Slider::new(&mut user_domain.playhead.time, 0.0..=duration).text("Interpolation"));
+                event_ticks(ui, &response, &user_domain.animation_events[user_domain.selected_animation], duration);
                 if ui.button("Reset Animation").clicked() {
                     user_domain.reset_animation();
                 }
             });
 
+            if let Some(animator) = &mut user_domain.animator {
+                ui.collapsing("State machine", |ui| {
+                    ui.checkbox(&mut user_domain.use_state_machine, "Use state machine");
+                    state_machine(ui, animator, &user_domain.animations);
+                });
+            }
+
+            ui.collapsing("Layers", |ui| {
+                let mut removed = None;
+                for (i, layer) in user_domain.layers.iter_mut().enumerate() {
+                    ui.push_id(i, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut layer.enabled, &user_domain.animations[layer.animation]);
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                        ui.add(Slider::new(&mut layer.weight, 0.0..=1.0).text("Weight"));
+                        let duration = user_domain.animations_duration[layer.animation];
+                        ui.add(Slider::new(&mut layer.reference_time, 0.0..=duration).text("Reference time"));
+                        mask_combo(ui, "Mask", &user_domain.masks, &mut layer.mask);
+                    });
+                    ui.separator();
+                }
+                if let Some(i) = removed {
+                    user_domain.layers.remove(i);
+                }
+
+                ComboBox::from_label("Add layer").selected_text("Animation").show_ui(ui, |ui| {
+                    for i in 0..user_domain.animations.len() {
+                        if ui.selectable_label(false, &user_domain.animations[i]).clicked() {
+                            user_domain.layers.push(AdditiveLayer::new(i));
+                        }
+                    }
+                });
+            });
+
+            ui.collapsing("Inverse kinematics", |ui| {
+                ui.add(Slider::new(&mut user_domain.ik_settings.iterations, 1..=50).text("Iterations"));
+                ui.add(Slider::new(&mut user_domain.ik_settings.tolerance, 0.0001..=0.1).logarithmic(true).text("Tolerance"));
+                ui.checkbox(&mut user_domain.draw_ik_debug, "Draw FABRIK bones");
+                ui.separator();
+
+                let mut removed = None;
+                for (i, chain) in user_domain.ik_chains.iter_mut().enumerate() {
+                    ui.push_id(i, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut chain.enabled, format!("Chain {}", i));
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                        ComboBox::from_label("Solver").selected_text(chain.solver.name()).show_ui(ui, |ui| {
+                            for solver in IkSolver::ALL {
+                                ui.selectable_value(&mut chain.solver, solver, solver.name());
+                            }
+                        });
+                        node_combo(ui, "Root", &user_domain.node_names, &mut chain.root);
+                        node_combo(ui, "Effector", &user_domain.node_names, &mut chain.effector);
+                        ui.horizontal(|ui| {
+                            ui.label("Target");
+                            ui.add(DragValue::new(&mut chain.target.x).speed(0.01));
+                            ui.add(DragValue::new(&mut chain.target.y).speed(0.01));
+                            ui.add(DragValue::new(&mut chain.target.z).speed(0.01));
+                        });
+                        if chain.solver == IkSolver::TwoBone {
+                            let mut use_pole = chain.pole.is_some();
+                            ui.checkbox(&mut use_pole, "Pole");
+                            match (use_pole, &mut chain.pole) {
+                                (true, Some(pole)) => {
+                                    ui.horizontal(|ui| {
+                                        ui.add(DragValue::new(&mut pole.x).speed(0.01));
+                                        ui.add(DragValue::new(&mut pole.y).speed(0.01));
+                                        ui.add(DragValue::new(&mut pole.z).speed(0.01));
+                                    });
+                                }
+                                (true, None) => chain.pole = Some(chain.target + Vec3::Z),
+                                (false, _) => chain.pole = None,
+                            }
+                            ui.add(Slider::new(&mut chain.softness, 0.0..=0.5).text("Softness"));
+                        }
+                        ui.label(format!("Distance to target {:.4}", chain.distance));
+                    });
+                    ui.separator();
+                }
+                if let Some(i) = removed {
+                    user_domain.ik_chains.remove(i);
+                    user_domain.dragged_ik_handle = None;
+                }
+                if ui.button("Add chain").clicked() {
+                    // In front of the camera to be grabbed right away
+                    let target = user_domain.camera.position + user_domain.camera.view_direction * 2.0;
+                    user_domain.ik_chains.push(IkChain::new(None, None, target, IkSolver::default()));
+                }
+            });
+
+            ui.collapsing("Look at", |ui| {
+                let mut removed = None;
+                for (i, look_at) in user_domain.look_ats.iter_mut().enumerate() {
+                    ui.push_id(i, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut look_at.enabled, format!("Look at {}", i));
+                            if ui.button("Remove").clicked() {
+                                removed = Some(i);
+                            }
+                        });
+                        ui.checkbox(&mut look_at.follow_camera, "Follow camera");
+                        ui.add_enabled_ui(!look_at.follow_camera, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Target");
+                                ui.add(DragValue::new(&mut look_at.target.x).speed(0.01));
+                                ui.add(DragValue::new(&mut look_at.target.y).speed(0.01));
+                                ui.add(DragValue::new(&mut look_at.target.z).speed(0.01));
+                            });
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Forward");
+                            ui.add(DragValue::new(&mut look_at.forward.x).speed(0.01));
+                            ui.add(DragValue::new(&mut look_at.forward.y).speed(0.01));
+                            ui.add(DragValue::new(&mut look_at.forward.z).speed(0.01));
+                        });
+                        ui.add(Slider::new(&mut look_at.smoothing, 0.0..=2.0).text("Smoothing"));
+
+                        let mut removed_joint = None;
+                        for (j, joint) in look_at.joints.iter_mut().enumerate() {
+                            ui.push_id(j, |ui| {
+                                ui.horizontal(|ui| {
+                                    node_combo(ui, "Joint", &user_domain.node_names, &mut joint.node);
+                                    if ui.button("Remove").clicked() {
+                                        removed_joint = Some(j);
+                                    }
+                                });
+                                ui.horizontal(|ui| {
+                                    ui.add(Slider::new(&mut joint.weight, 0.0..=1.0).text("Weight"));
+                                    ui.label("Limit");
+                                    ui.drag_angle(&mut joint.limit);
+                                    joint.limit = joint.limit.clamp(0.0, std::f32::consts::PI);
+                                });
+                            });
+                        }
+                        if let Some(j) = removed_joint {
+                            look_at.joints.remove(j);
+                        }
+                        if ui.button("Add joint").clicked() {
+                            look_at.joints.push(LookAtJoint::new(None, 1.0, std::f32::consts::FRAC_PI_2));
+                        }
+                        ui.label(format!("Angle to target {:.1}°", look_at.angle.to_degrees()));
+                    });
+                    ui.separator();
+                }
+                if let Some(i) = removed {
+                    user_domain.look_ats.remove(i);
+                }
+                if ui.button("Add look at").clicked() {
+                    let target = user_domain.camera.position + user_domain.camera.view_direction * 2.0;
+                    user_domain.look_ats.push(LookAt::new(Vec::new(), Vec3::Z, target));
+                }
+            });
+
+            ui.collapsing("Foot IK", |ui| {
+                ui.checkbox(&mut user_domain.foot_ik, "Plant the feet");
+                ui.checkbox(&mut user_domain.draw_ground, "Draw ground");
+                let center = user_domain.start_pos;
+                ComboBox::from_label("Ground").selected_text(user_domain.ground.name()).show_ui(ui, |ui| {
+                    if ui.selectable_label(matches!(user_domain.ground, Ground::Plane { .. }), "Plane").clicked() {
+                        user_domain.ground = Ground::plane(center);
+                    }
+                    if ui.selectable_label(matches!(user_domain.ground, Ground::Heightfield(_)), "Heightfield").clicked() {
+                        user_domain.ground = Ground::Heightfield(Heightfield::hills(center, 8.0, 17, 0.15));
+                    }
+                });
+                if let Ground::Plane { point, tilt } = &mut user_domain.ground {
+                    ui.add(Slider::new(&mut point.y, -5.0..=5.0).text("Height"));
+                    ui.add(Slider::new(&mut tilt.x, -0.5..=0.5).text("Tilt x"));
+                    ui.add(Slider::new(&mut tilt.y, -0.5..=0.5).text("Tilt z"));
+                }
+                ui.label(format!("Contacts {}", user_domain.foot_contacts.len()));
+            });
+
+            ui.collapsing("Masks", |ui| {
+                let mut removed = None;
+                for i in 0..user_domain.masks.len() {
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(&mut user_domain.edited_mask, Some(i), &user_domain.masks[i].name);
+                        if ui.button("Remove").clicked() {
+                            removed = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = removed {
+                    user_domain.remove_mask(i);
+                }
+
+                ComboBox::from_label("New mask from node").selected_text("Node").show_ui(ui, |ui| {
+                    for (i, name) in user_domain.node_names.iter().enumerate() {
+                        if ui.selectable_label(false, name).clicked() {
+                            let mask = BoneMask::from_subtree(name.clone(), &user_domain.node_parents, i);
+                            user_domain.masks.push(mask);
+                            user_domain.edited_mask = Some(user_domain.masks.len() - 1);
+                        }
+                    }
+                });
+
+                if let Some(mask) = user_domain.edited_mask.and_then(|i| user_domain.masks.get_mut(i)) {
+                    ui.separator();
+                    ui.text_edit_singleline(&mut mask.name);
+                    let mut children = vec![Vec::new(); user_domain.node_parents.len()];
+                    for (node, parent) in user_domain.node_parents.iter().enumerate() {
+                        if let Some(parent) = parent {
+                            children[*parent].push(node);
+                        }
+                    }
+                    for (node, parent) in user_domain.node_parents.iter().enumerate() {
+                        if parent.is_none() {
+                            node_hierarchy(ui, node, &user_domain.node_names, &user_domain.node_parents, &children, mask);
+                        }
+                    }
+                }
+            });
+
+            ui.collapsing("Events", |ui| {
+                if ui.button("Clear").clicked() {
+                    user_domain.event_queue.clear();
+                }
+                for event in user_domain.event_queue.iter().rev() {
+                    ui.label(format!("{:.2}s {} ({})", event.time, event.name, user_domain.animations[event.animation]));
+                }
+            });
+
             ui.collapsing("Light", |ui| {
                 ui.label("Position");
                 ui.horizontal(|ui| {
@@ -117,3 +413,140 @@ pub fn gui(user_domain: &mut UserDomain, ui: &Context) {
             });
         });
 }
+
+/// Draw a tick over the slider rail at the time of each event
+fn event_ticks(ui: &Ui, slider: &Response, events: &[(String, f32)], duration: f32) {
+    if duration <= 0.0 {
+        return;
+    }
+    let rect = slider.rect;
+    let handle_radius = rect.height() / 2.5;
+    let left = rect.left() + handle_radius;
+    let width = ui.spacing().slider_width - 2.0 * handle_radius;
+    let stroke = Stroke::new(2.0, Color32::YELLOW);
+    for (name, time) in events {
+        let x = left + width * (time / duration).clamp(0.0, 1.0);
+        ui.painter().line_segment([pos2(x, rect.top()), pos2(x, rect.top() + handle_radius)], stroke);
+        if slider.hovered() {
+            ui.painter().text(pos2(x, rect.top()), Align2::CENTER_BOTTOM, name, FontId::default(), Color32::YELLOW);
+        }
+    }
+}
+
+fn node_combo(ui: &mut Ui, label: &str, names: &[String], node: &mut Option<usize>) {
+    let selected_text = node.and_then(|i| names.get(i)).map_or("None", |name| name.as_str());
+    ComboBox::from_label(label).selected_text(selected_text).show_ui(ui, |ui| {
+        for (i, name) in names.iter().enumerate() {
+            ui.selectable_value(node, Some(i), name);
+        }
+    });
+}
+
+fn mask_combo(ui: &mut Ui, label: &str, masks: &[BoneMask], mask: &mut Option<usize>) {
+    let selected_text = match mask.and_then(|i| masks.get(i)) {
+        Some(mask) => mask.name.as_str(),
+        None => "None",
+    };
+    ComboBox::from_label(label).selected_text(selected_text).show_ui(ui, |ui| {
+        ui.selectable_value(mask, None, "None");
+        for (i, m) in masks.iter().enumerate() {
+            ui.selectable_value(mask, Some(i), &m.name);
+        }
+    });
+}
+
+/// Weight of a node in the mask, followed by its children
+fn node_hierarchy(
+    ui: &mut Ui, node: usize, names: &[String], parents: &[Option<usize>], children: &[Vec<usize>], mask: &mut BoneMask,
+) {
+    let weight_row = |ui: &mut Ui, mask: &mut BoneMask| {
+        ui.add(Slider::new(&mut mask.weights[node], 0.0..=1.0));
+        if !children[node].is_empty() && ui.button("Set children").clicked() {
+            mask.set_subtree(parents, node, mask.weights[node]);
+        }
+    };
+
+    let name = match names[node].as_str() {
+        "" => format!("Node {}", node),
+        name => name.to_string(),
+    };
+    if children[node].is_empty() {
+        ui.horizontal(|ui| {
+            ui.label(name);
+            weight_row(ui, mask);
+        });
+    } else {
+        CollapsingHeader::new(name).id_salt(node).show(ui, |ui| {
+            ui.horizontal(|ui| weight_row(ui, mask));
+            for child in children[node].iter() {
+                node_hierarchy(ui, *child, names, parents, children, mask);
+            }
+        });
+    }
+}
+
+/// Parameters of the state machine, its states with the active one highlighted and the transition in flight
+fn state_machine(ui: &mut Ui, animator: &mut Animator, animations: &[String]) {
+    ui.label("Parameters");
+    for parameter in animator.parameters.iter_mut() {
+        match &mut parameter.value {
+            ParameterValue::Float(value) => {
+                ui.add(Slider::new(value, -1.0..=1.0).text(&parameter.name));
+            }
+            ParameterValue::Bool(value) => {
+                ui.checkbox(value, &parameter.name);
+            }
+            ParameterValue::Trigger(value) => {
+                ui.horizontal(|ui| {
+                    if ui.button(&parameter.name).clicked() {
+                        *value = true;
+                    }
+                    if *value {
+                        ui.label("set");
+                    }
+                });
+            }
+        }
+    }
+
+    ui.separator();
+    ui.label("States");
+    let from_state = animator.active_transition.as_ref().map(|active| active.from_state);
+    for (i, state) in animator.states.iter().enumerate() {
+        let text = RichText::new(&state.name);
+        let text = if i == animator.current_state {
+            text.strong().color(Color32::LIGHT_GREEN)
+        } else if Some(i) == from_state {
+            text.color(Color32::YELLOW)
+        } else {
+            text
+        };
+        ui.label(text);
+    }
+
+    ui.separator();
+    let current = &animator.states[animator.current_state];
+    ui.label(format!(
+        "{}: {:.2} / {:.2}",
+        current.name, animator.playhead.time, current.duration
+    ));
+    if let Motion::BlendSpace(blend_space) = current.motion {
+        let blend_space = &animator.blend_spaces[blend_space];
+        for (sample, weight) in blend_space.samples.iter().zip(blend_space.weights.iter()) {
+            ui.label(format!("{}: {:.2}", animations[sample.animation], weight));
+        }
+    }
+    match &animator.active_transition {
+        Some(active) => {
+            ui.label(format!(
+                "Transition {} -> {} ({:.0}%)",
+                animator.states[active.from_state].name,
+                current.name,
+                active.fade.weight() * 100.0
+            ));
+        }
+        None => {
+            ui.label("No transition");
+        }
+    }
+}
@@ -0,0 +1,21 @@
+// Joint influences of the vertices with 4 joints at most, prepended to the skinning shaders
+
+// Sets of 4 joints affecting a vertex
+const INFLUENCE_SETS: u32 = 1u;
+
+struct Influences {
+    @location(3) affected_joints: vec4<u32>,
+    @location(4) joint_weights: vec4<f32>,
+};
+
+fn makeInfluences(affected_joints: array<vec4<u32>, 2>, joint_weights: array<vec4<f32>, 2>) -> Influences {
+    return Influences(affected_joints[0], joint_weights[0]);
+}
+
+fn influenceJoints(influences: Influences, influence_set: u32) -> vec4<u32> {
+    return influences.affected_joints;
+}
+
+fn influenceWeights(influences: Influences, influence_set: u32) -> vec4<f32> {
+    return influences.joint_weights;
+}
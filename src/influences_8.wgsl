@@ -0,0 +1,23 @@
+// Joint influences of the vertices with up to 8 joints, prepended to the skinning shaders
+
+// Sets of 4 joints affecting a vertex
+const INFLUENCE_SETS: u32 = 2u;
+
+struct Influences {
+    @location(3) affected_joints: vec4<u32>,
+    @location(4) joint_weights: vec4<f32>,
+    @location(10) affected_joints_1: vec4<u32>,
+    @location(11) joint_weights_1: vec4<f32>,
+};
+
+fn makeInfluences(affected_joints: array<vec4<u32>, 2>, joint_weights: array<vec4<f32>, 2>) -> Influences {
+    return Influences(affected_joints[0], joint_weights[0], affected_joints[1], joint_weights[1]);
+}
+
+fn influenceJoints(influences: Influences, influence_set: u32) -> vec4<u32> {
+    return select(influences.affected_joints, influences.affected_joints_1, influence_set == 1u);
+}
+
+fn influenceWeights(influences: Influences, influence_set: u32) -> vec4<f32> {
+    return select(influences.joint_weights, influences.joint_weights_1, influence_set == 1u);
+}
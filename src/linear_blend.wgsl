@@ -0,0 +1,17 @@
+// Linear blend skinning, prepended to the shaders defining `linearJoint` after the influences of their layout
+
+fn blendJoints(affected_joints: vec4<u32>, weights: vec4<f32>) -> mat4x4<f32> {
+    return weights.x * linearJoint(affected_joints.x) +
+        weights.y * linearJoint(affected_joints.y) +
+        weights.z * linearJoint(affected_joints.z) +
+        weights.w * linearJoint(affected_joints.w);
+}
+
+fn linearSkinMat(influences: Influences) -> mat4x4<f32> {
+    var skinMat = mat4x4<f32>();
+    for (var influence_set = 0u; influence_set < INFLUENCE_SETS; influence_set++) {
+        let affected_joints = influenceJoints(influences, influence_set);
+        skinMat += blendJoints(affected_joints, influenceWeights(influences, influence_set));
+    }
+    return skinMat;
+}
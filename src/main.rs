@@ -2,10 +2,13 @@ mod basic_object;
 mod camera;
 mod color;
 mod data;
+mod ground;
 mod gui;
 mod hermite_spline;
 mod light;
 mod model;
+mod playback;
+mod skinning;
 mod state;
 mod texture;
 mod utils_glam;
@@ -24,13 +27,25 @@ use egui_winit::winit::{
     keyboard::{KeyCode, PhysicalKey},
 };
 use log::{error, warn};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-#[derive(Default)]
 pub struct App {
     pub window: Option<Arc<Window>>,
     pub state: Option<State>,
+    pub model_path: PathBuf,
 }
+
+impl App {
+    pub fn new(model_path: PathBuf) -> Self {
+        Self {
+            window: None,
+            state: None,
+            model_path,
+        }
+    }
+}
+
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let window_attributes = WindowAttributes::default()
@@ -40,7 +55,7 @@ impl ApplicationHandler for App {
             Ok(window) => {
                 let rc_window = Arc::new(window);
                 self.window = Some(rc_window.clone());
-                let state = pollster::block_on(State::new(rc_window));
+                let state = pollster::block_on(State::new(rc_window, &self.model_path));
                 self.state = Some(state);
             }
             Err(_) => {}
@@ -115,7 +130,13 @@ fn main() -> Result<()> {
     let event_loop = EventLoop::new().context("Error creating the event loop")?;
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let mut app = App::default();
+    // The glTF file to display can be given as the first argument
+    let model_path = match std::env::args().nth(1) {
+        Some(path) => PathBuf::from(path),
+        None => Path::new("rsc").join("Woman.gltf"),
+    };
+
+    let mut app = App::new(model_path);
     event_loop.run_app(&mut app).context("Error running the app")?;
 
     Ok(())
@@ -1,41 +1,45 @@
-use crate::model::nodes_tree::Node;
+use crate::model::pose::{NodePose, Pose};
 use glam::Quat;
+use serde::Deserialize;
+use std::cell::Cell;
 use std::ops::{Add, Mul, Sub};
 
 pub struct Animation {
     pub name: String,
     pub channels: Vec<Option<NodeChannels>>,
-    duration: f32
+    /// Markers sorted by time
+    pub events: Vec<AnimationEvent>,
+    duration: f32,
+}
+
+/// Named marker on the timeline of an animation, such as a footstep
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct AnimationEvent {
+    pub name: String,
+    pub time: f32,
 }
 
 impl Animation {
     pub fn new(name: String, channels: Vec<Option<NodeChannels>>) -> Self {
         let mut max_duration = 0.0;
-        for channel in &channels {
-            if let Some(channel) = channel {
-                if let Some(translation) = &channel.translation {
-                    let duration = *translation.times.last().unwrap();
-                    if duration > max_duration {
-                        max_duration = duration;
-                    }
-                }
-                if let Some(rotation) = &channel.rotation {
-                    let duration = *rotation.times.last().unwrap();
-                    if duration > max_duration {
-                        max_duration = duration;
-                    }
-                }
-                if let Some(scale) = &channel.scale {
-                    let duration = *scale.times.last().unwrap();
-                    if duration > max_duration {
-                        max_duration = duration;
-                    }
+        for channel in channels.iter().flatten() {
+            let node_channels = [
+                &channel.translation,
+                &channel.rotation,
+                &channel.scale,
+                &channel.weights,
+            ];
+            for channel in node_channels.into_iter().flatten() {
+                let duration = *channel.times.last().unwrap();
+                if duration > max_duration {
+                    max_duration = duration;
                 }
             }
         }
         Self {
             name,
             channels,
+            events: Vec::new(),
             duration: max_duration,
         }
     }
@@ -43,6 +47,37 @@ impl Animation {
     pub fn duration(&self) -> f32 {
         self.duration
     }
+
+    pub fn add_events(&mut self, events: impl IntoIterator<Item = AnimationEvent>) {
+        self.events.extend(events);
+        self.events.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    /// Events crossed by the playback moving from `from` to `to`. The start of the interval is included and its
+    /// end excluded, so a sequence of contiguous intervals crosses every event once. The end of the animation is
+    /// included when it is reached.
+    pub fn crossed_events(&self, from: f32, to: f32) -> impl Iterator<Item = &AnimationEvent> {
+        let duration = self.duration;
+        self.events.iter().filter(move |event| {
+            let t = event.time;
+            if from < to {
+                (from <= t && t < to) || (t == to && to >= duration)
+            } else if from > to {
+                (to < t && t <= from) || (t == to && to <= 0.0)
+            } else {
+                false
+            }
+        })
+    }
+
+    /// Evaluate the channels at `t` into the nodes they animate, the other nodes of the pose are left as is
+    pub fn sample(&self, t: f32, pose: &mut Pose) {
+        for (channels, node) in self.channels.iter().zip(pose.nodes.iter_mut()) {
+            if let Some(channels) = channels {
+                channels.eval(t, node);
+            }
+        }
+    }
 }
 
 #[derive(Default, Clone)]
@@ -50,10 +85,11 @@ pub struct NodeChannels {
     pub translation: Option<Channel>,
     pub rotation: Option<Channel>,
     pub scale: Option<Channel>,
+    pub weights: Option<Channel>,
 }
 
 impl NodeChannels {
-    pub fn eval(&self, t: f32, node: &mut Node) {
+    pub fn eval(&self, t: f32, node: &mut NodePose) {
         if let Some(channel) = &self.translation {
             channel.eval(t, node);
         }
@@ -63,6 +99,9 @@ impl NodeChannels {
         if let Some(channel) = &self.scale {
             channel.eval(t, node);
         }
+        if let Some(channel) = &self.weights {
+            channel.eval(t, node);
+        }
     }
 }
 
@@ -79,6 +118,14 @@ impl InterpolationType {
     where
         T: Mul<f32, Output = T> + Add<T, Output = T> + Copy + Sub<T, Output = T>,
     {
+        // Before the first key or after the last one
+        if indexes.0 == indexes.1 {
+            return match self {
+                InterpolationType::CUBICSPLINE => values[indexes.0 * 3 + 1],
+                _ => values[indexes.0],
+            };
+        }
+
         match self {
             InterpolationType::STEP => values[indexes.0],
             InterpolationType::LINEAR => {
@@ -115,30 +162,40 @@ impl InterpolationType {
         }
     }
 
-    pub fn s_interpolate(&self, values: &Vec<Quat>, timings: &Vec<f32>, indexes: (usize, usize), time: f32) -> Quat {
+    /// Interpolate the morph target weights, the values hold `weights.len()` weights per key
+    pub fn interpolate_weights(
+        &self, values: &[f32], timings: &[f32], indexes: (usize, usize), time: f32, weights: &mut [f32],
+    ) {
+        let count = weights.len();
+        if indexes.0 == indexes.1 {
+            let key = match self {
+                InterpolationType::CUBICSPLINE => indexes.0 * 3 + 1,
+                _ => indexes.0,
+            };
+            weights.copy_from_slice(&values[key * count..(key + 1) * count]);
+            return;
+        }
+
         match self {
-            InterpolationType::STEP => values[indexes.0],
+            InterpolationType::STEP => {
+                weights.copy_from_slice(&values[indexes.0 * count..(indexes.0 + 1) * count]);
+            }
             InterpolationType::LINEAR => {
                 let prev_time = timings[indexes.0];
                 let next_time = timings[indexes.1];
                 let t = (time - prev_time) / (next_time - prev_time);
-                let prev = values[indexes.0];
-                let next = values[indexes.1];
-                prev.slerp(next, t)
+                for (k, weight) in weights.iter_mut().enumerate() {
+                    let prev = values[indexes.0 * count + k];
+                    let next = values[indexes.1 * count + k];
+                    *weight = prev * (1.0 - t) + next * t;
+                }
             }
             InterpolationType::CUBICSPLINE => {
-                /*let prev_time = timings[indexes.0];
+                let prev_time = timings[indexes.0];
                 let next_time = timings[indexes.1];
                 let delta_time = next_time - prev_time;
 
-                let prev_tangent = values[indexes.0 * 3 + 2] * delta_time;
-                let next_tangent = values[indexes.1 * 3 + 0] * delta_time;
-
                 let t = (time - prev_time) / delta_time;
-
-                let prev_point = values[indexes.0 * 3 + 1];
-                let next_point = values[indexes.1 * 3 + 1];
-
                 let t2 = t * t;
                 let t3 = t2 * t;
 
@@ -146,9 +203,39 @@ impl InterpolationType {
                 let h10 = t3 - 2.0 * t2 + t;
                 let h01 = -2.0 * t3 + 3.0 * t2;
                 let h11 = t3 - t2;
-                prev_point * h00 + prev_tangent * h10 + next_point * h01 + next_tangent * h11
-                */
-                unimplemented!("Need to implement cubic slerp for quaternions");
+
+                // Each key holds the in tangents, the values and the out tangents
+                let prev_key = indexes.0 * 3 * count;
+                let next_key = indexes.1 * 3 * count;
+                for (k, weight) in weights.iter_mut().enumerate() {
+                    let prev_point = values[prev_key + count + k];
+                    let prev_tangent = values[prev_key + 2 * count + k] * delta_time;
+                    let next_tangent = values[next_key + k] * delta_time;
+                    let next_point = values[next_key + count + k];
+                    *weight = prev_point * h00 + prev_tangent * h10 + next_point * h01 + next_tangent * h11;
+                }
+            }
+        }
+    }
+
+    pub fn s_interpolate(&self, values: &Vec<Quat>, timings: &Vec<f32>, indexes: (usize, usize), time: f32) -> Quat {
+        if indexes.0 == indexes.1 {
+            return self.interpolate(values, timings, indexes, time);
+        }
+
+        match self {
+            InterpolationType::STEP => values[indexes.0],
+            InterpolationType::LINEAR => {
+                let prev_time = timings[indexes.0];
+                let next_time = timings[indexes.1];
+                let t = (time - prev_time) / (next_time - prev_time);
+                let prev = values[indexes.0];
+                let next = values[indexes.1];
+                prev.slerp(next, t)
+            }
+            InterpolationType::CUBICSPLINE => {
+                // As defined by glTF, the quaternion is interpolated component wise and normalized
+                self.interpolate(values, timings, indexes, time).normalize()
             }
         }
     }
@@ -159,6 +246,8 @@ pub enum ChannelType {
     Translation(Vec<glam::Vec3>),
     Rotation(Vec<glam::Quat>),
     Scale(Vec<glam::Vec3>),
+    /// Morph target weights of every key, one after the other
+    Weights(Vec<f32>),
 }
 
 impl Default for ChannelType {
@@ -172,10 +261,21 @@ pub struct Channel {
     pub interpolation: InterpolationType,
     pub times: Vec<f32>,
     pub values: ChannelType,
+    /// Key found by the last evaluation, the next one is usually on the same or the next key
+    cursor: Cell<usize>,
 }
 
 impl Channel {
-    pub fn eval(&self, t: f32, node: &mut Node) {
+    pub fn new(interpolation: InterpolationType, times: Vec<f32>, values: ChannelType) -> Self {
+        Self {
+            interpolation,
+            times,
+            values,
+            cursor: Cell::new(0),
+        }
+    }
+
+    pub fn eval(&self, t: f32, node: &mut NodePose) {
         match &self.values {
             ChannelType::Translation(translation) => {
                 let indexes = self.get_indexes(t);
@@ -189,17 +289,236 @@ impl Channel {
                 let indexes = self.get_indexes(t);
                 node.scale = self.interpolation.interpolate(scale, &self.times, indexes, t);
             }
+            ChannelType::Weights(weights) => {
+                let indexes = self.get_indexes(t);
+                self.interpolation
+                    .interpolate_weights(weights, &self.times, indexes, t, &mut node.weights);
+            }
         }
     }
 
     fn get_indexes(&self, t: f32) -> (usize, usize) {
-        let mut prev = 0;
-        for i in 0..self.times.len() {
-            if self.times[i] >= t {
-                return (prev, i);
+        let indexes = find_keys(&self.times, t, Some(self.cursor.get()));
+        self.cursor.set(indexes.0);
+        indexes
+    }
+}
+
+/// Find the keys surrounding `t`. Before the first key and after the last one, both indexes are the same key.
+/// The search starts by checking the keys at and after `hint`, then falls back to a binary search.
+/// Without keys, both indexes are 0.
+pub fn find_keys(times: &[f32], t: f32, hint: Option<usize>) -> (usize, usize) {
+    let Some(last) = times.len().checked_sub(1) else {
+        return (0, 0);
+    };
+    if t <= times[0] {
+        return (0, 0);
+    }
+    if t >= times[last] {
+        return (last, last);
+    }
+
+    if let Some(hint) = hint {
+        for prev in [hint, hint + 1] {
+            if prev < last && times[prev] <= t && t < times[prev + 1] {
+                return (prev, prev + 1);
             }
-            prev = i;
         }
-        (0, 0)
+    }
+
+    // First key after t, it is never the first key since t > times[0]
+    let next = times.partition_point(|time| *time <= t);
+    (next - 1, next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+
+    fn weights_channel(interpolation: InterpolationType, values: Vec<f32>) -> Channel {
+        Channel::new(interpolation, vec![0.0, 1.0], ChannelType::Weights(values))
+    }
+
+    #[test]
+    fn test_find_keys() {
+        let times = [0.0, 1.0, 2.0, 3.0, 4.0];
+
+        assert_eq!(find_keys(&times, -1.0, None), (0, 0));
+        assert_eq!(find_keys(&times, 0.0, None), (0, 0));
+        assert_eq!(find_keys(&times, 0.5, None), (0, 1));
+        assert_eq!(find_keys(&times, 1.0, None), (1, 2));
+        assert_eq!(find_keys(&times, 3.5, None), (3, 4));
+        assert_eq!(find_keys(&times, 4.0, None), (4, 4));
+        assert_eq!(find_keys(&times, 10.0, None), (4, 4));
+
+        // A wrong hint falls back to the search
+        assert_eq!(find_keys(&times, 2.5, Some(2)), (2, 3));
+        assert_eq!(find_keys(&times, 3.5, Some(2)), (3, 4));
+        assert_eq!(find_keys(&times, 0.5, Some(3)), (0, 1));
+        assert_eq!(find_keys(&times, 2.5, Some(10)), (2, 3));
+
+        assert_eq!(find_keys(&[], 1.0, None), (0, 0));
+        assert_eq!(find_keys(&[], 1.0, Some(3)), (0, 0));
+    }
+
+    #[test]
+    fn test_eval_clamped_at_ends() {
+        let channel = Channel::new(
+            InterpolationType::LINEAR,
+            vec![1.0, 2.0],
+            ChannelType::Translation(vec![glam::Vec3::X, glam::Vec3::Y]),
+        );
+        let mut node = NodePose::default();
+
+        channel.eval(0.0, &mut node);
+        assert_eq!(node.translate, glam::Vec3::X);
+        channel.eval(1.5, &mut node);
+        assert_eq!(node.translate, glam::Vec3::new(0.5, 0.5, 0.0));
+        // Past the last key, the pose stays on the last key instead of going back to the first one
+        channel.eval(3.0, &mut node);
+        assert_eq!(node.translate, glam::Vec3::Y);
+        channel.eval(0.5, &mut node);
+        assert_eq!(node.translate, glam::Vec3::X);
+    }
+
+    #[test]
+    fn test_weights_linear() {
+        let channel = weights_channel(InterpolationType::LINEAR, vec![0.0, 1.0, 1.0, 0.0]);
+        let mut node = NodePose {
+            weights: vec![0.0; 2],
+            ..Default::default()
+        };
+
+        channel.eval(0.25, &mut node);
+        assert_eq!(node.weights, vec![0.25, 0.75]);
+    }
+
+    fn rotation_channel(times: Vec<f32>, values: Vec<Quat>) -> Channel {
+        Channel::new(InterpolationType::CUBICSPLINE, times, ChannelType::Rotation(values))
+    }
+
+    /// Derivative of a rotation around Y of `angle` radians turning at `speed` radians per second
+    fn rotation_y_tangent(angle: f32, speed: f32) -> Quat {
+        let k = speed / 2.0;
+        Quat::from_xyzw(0.0, k * (angle / 2.0).cos(), 0.0, -k * (angle / 2.0).sin())
+    }
+
+    fn assert_quat_close(a: Quat, b: Quat) {
+        assert!(a.abs_diff_eq(b, 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_rotation_cubic_spline_flat_tangents() {
+        let channel = rotation_channel(
+            vec![0.0, 1.0],
+            vec![
+                Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+                Quat::IDENTITY,
+                Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+                Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+                Quat::from_rotation_y(FRAC_PI_2),
+                Quat::from_xyzw(0.0, 0.0, 0.0, 0.0),
+            ],
+        );
+        let mut node = NodePose::default();
+
+        channel.eval(0.0, &mut node);
+        assert_quat_close(node.rotate, Quat::IDENTITY);
+        channel.eval(0.5, &mut node);
+        assert_quat_close(node.rotate, Quat::from_rotation_y(FRAC_PI_4));
+        channel.eval(1.0, &mut node);
+        assert_quat_close(node.rotate, Quat::from_rotation_y(FRAC_PI_2));
+    }
+
+    #[test]
+    fn test_rotation_cubic_spline_tangents() {
+        // Quarter turn in two seconds with the tangents of a constant rotation speed. The reference values are the
+        // glTF cubic spline formula evaluated in double precision.
+        let speed = FRAC_PI_4;
+        let channel = rotation_channel(
+            vec![1.0, 3.0],
+            vec![
+                rotation_y_tangent(0.0, speed),
+                Quat::IDENTITY,
+                rotation_y_tangent(0.0, speed),
+                rotation_y_tangent(FRAC_PI_2, speed),
+                Quat::from_rotation_y(FRAC_PI_2),
+                rotation_y_tangent(FRAC_PI_2, speed),
+            ],
+        );
+        let mut node = NodePose::default();
+
+        channel.eval(1.5, &mut node);
+        assert_quat_close(node.rotate, Quat::from_xyzw(0.0, 0.195_005_74, 0.0, 0.980_802_1));
+        channel.eval(2.0, &mut node);
+        assert_quat_close(node.rotate, Quat::from_xyzw(0.0, 0.382_683_43, 0.0, 0.923_879_5));
+        assert!((node.rotate.length() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_weights_cubic_spline() {
+        // Flat tangents, the middle is halfway between the two keys
+        #[rustfmt::skip]
+        let values = vec![
+            0.0, 0.0, 0.0, 1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0, 0.0, 0.0, 0.0,
+        ];
+        let channel = weights_channel(InterpolationType::CUBICSPLINE, values);
+        let mut node = NodePose {
+            weights: vec![0.0; 2],
+            ..Default::default()
+        };
+
+        channel.eval(0.5, &mut node);
+        assert_eq!(node.weights, vec![0.5, 0.5]);
+
+        channel.eval(0.25, &mut node);
+        assert!((node.weights[0] - 0.15625).abs() < 1e-6);
+        assert!((node.weights[1] - 0.84375).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_crossed_events() {
+        let channel = Channel::new(
+            InterpolationType::LINEAR,
+            vec![0.0, 1.0],
+            ChannelType::Weights(vec![0.0, 0.0]),
+        );
+        let channels = vec![Some(NodeChannels {
+            weights: Some(channel),
+            ..Default::default()
+        })];
+        let mut animation = Animation::new("walk".to_string(), channels);
+        animation.add_events([
+            AnimationEvent {
+                name: "right".to_string(),
+                time: 0.5,
+            },
+            AnimationEvent {
+                name: "left".to_string(),
+                time: 0.0,
+            },
+            AnimationEvent {
+                name: "end".to_string(),
+                time: 1.0,
+            },
+        ]);
+        let names = |from, to| -> Vec<String> {
+            animation
+                .crossed_events(from, to)
+                .map(|event| event.name.clone())
+                .collect()
+        };
+
+        assert_eq!(names(0.0, 0.5), vec!["left"]);
+        assert_eq!(names(0.5, 0.7), vec!["right"]);
+        assert_eq!(names(0.7, 0.7), Vec::<String>::new());
+        // Loop wrap, swept as (0.8, 1.0) then (0.0, 0.3)
+        assert_eq!(names(0.8, 1.0), vec!["end"]);
+        assert_eq!(names(0.0, 0.3), vec!["left"]);
+        // Playing in reverse or scrubbing back
+        assert_eq!(names(0.7, 0.0), vec!["left", "right"]);
+        assert_eq!(names(1.0, 0.6), vec!["end"]);
     }
 }
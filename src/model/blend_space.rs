@@ -0,0 +1,207 @@
+use crate::model::pose::Pose;
+use crate::model::Modelv2;
+use glam::Vec2;
+
+/// Animation placed at a position of a blend space
+#[derive(Clone, Debug)]
+pub struct BlendSample {
+    pub animation: usize,
+    pub duration: f32,
+    pub position: Vec2,
+}
+
+/// Several animations weighted from their distance to a position, only x is used in one dimension
+#[derive(Clone, Debug)]
+pub struct BlendSpace {
+    pub name: String,
+    pub samples: Vec<BlendSample>,
+    pub two_dimensions: bool,
+    pub position: Vec2,
+    /// Weight of each sample at `position`, their sum is 1.0
+    pub weights: Vec<f32>,
+}
+
+impl BlendSpace {
+    pub fn new(name: String, samples: Vec<BlendSample>, two_dimensions: bool) -> Self {
+        let mut blend_space = Self {
+            name,
+            samples,
+            two_dimensions,
+            position: Vec2::ZERO,
+            weights: Vec::new(),
+        };
+        blend_space.set_position(Vec2::ZERO);
+        blend_space
+    }
+
+    pub fn set_position(&mut self, position: Vec2) {
+        self.position = position;
+        self.weights = if self.two_dimensions {
+            let positions: Vec<Vec2> = self.samples.iter().map(|s| s.position).collect();
+            weights_2d(&positions, position)
+        } else {
+            let positions: Vec<f32> = self.samples.iter().map(|s| s.position.x).collect();
+            weights_1d(&positions, position.x)
+        };
+    }
+
+    /// Duration of a cycle, the durations of the samples weighted. The animations are stretched to it so their
+    /// phases stay aligned.
+    pub fn duration(&self) -> f32 {
+        self.samples
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(sample, weight)| sample.duration * weight)
+            .sum()
+    }
+
+    /// Sample with the most weight
+    pub fn main_sample(&self) -> Option<&BlendSample> {
+        self.samples
+            .iter()
+            .zip(self.weights.iter())
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(sample, _)| sample)
+    }
+
+    /// Blend the samples with every animation at the same `phase`, from 0.0 to 1.0
+    pub fn sample_pose(&self, model: &Modelv2, phase: f32) -> Pose {
+        let mut pose: Option<Pose> = None;
+        let mut total_weight = 0.0;
+        for (sample, weight) in self.samples.iter().zip(self.weights.iter()) {
+            if *weight <= 0.0 {
+                continue;
+            }
+            let sample_pose = model.sample_pose(sample.animation, phase * sample.duration);
+            total_weight += weight;
+            match &mut pose {
+                Some(pose) => pose.blend(&sample_pose, weight / total_weight),
+                None => pose = Some(sample_pose),
+            }
+        }
+        pose.unwrap_or_else(|| model.bind_pose())
+    }
+}
+
+/// Linear interpolation between the two samples around `x`, the ends are clamped
+pub fn weights_1d(positions: &[f32], x: f32) -> Vec<f32> {
+    let mut weights = vec![0.0; positions.len()];
+    if positions.is_empty() {
+        return weights;
+    }
+
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    order.sort_by(|a, b| positions[*a].total_cmp(&positions[*b]));
+    let first = order[0];
+    let last = order[order.len() - 1];
+    if x <= positions[first] {
+        weights[first] = 1.0;
+        return weights;
+    }
+    if x >= positions[last] {
+        weights[last] = 1.0;
+        return weights;
+    }
+
+    for pair in order.windows(2) {
+        let (prev, next) = (pair[0], pair[1]);
+        if x <= positions[next] {
+            let t = (x - positions[prev]) / (positions[next] - positions[prev]);
+            weights[prev] = 1.0 - t;
+            weights[next] = t;
+            break;
+        }
+    }
+    weights
+}
+
+/// Gradient band interpolation, each sample weight falls off toward the other samples
+/// The positions should be distinct, two samples at the same place have no edge to fall off along
+pub fn weights_2d(positions: &[Vec2], position: Vec2) -> Vec<f32> {
+    let mut weights: Vec<f32> = positions
+        .iter()
+        .enumerate()
+        .map(|(i, pi)| {
+            let to_position = position - *pi;
+            positions
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, pj)| {
+                    let edge = *pj - *pi;
+                    1.0 - to_position.dot(edge) / edge.length_squared()
+                })
+                .fold(1.0_f32, f32::min)
+                .max(0.0)
+        })
+        .collect();
+
+    let total: f32 = weights.iter().sum();
+    if total > 0.0 {
+        for weight in weights.iter_mut() {
+            *weight /= total;
+        }
+    }
+    weights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_weights(weights: Vec<f32>, expected: &[f32]) {
+        assert_eq!(weights.len(), expected.len());
+        for (weight, expected) in weights.iter().zip(expected) {
+            assert!((weight - expected).abs() < 1e-5, "{:?} != {:?}", weights, expected);
+        }
+    }
+
+    #[test]
+    fn test_weights_1d() {
+        // Not sorted
+        let positions = [1.0, 0.0, 0.5];
+        assert_weights(weights_1d(&positions, -1.0), &[0.0, 1.0, 0.0]);
+        assert_weights(weights_1d(&positions, 0.25), &[0.0, 0.5, 0.5]);
+        assert_weights(weights_1d(&positions, 0.5), &[0.0, 0.0, 1.0]);
+        assert_weights(weights_1d(&positions, 0.75), &[0.5, 0.0, 0.5]);
+        assert_weights(weights_1d(&positions, 2.0), &[1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_weights_2d() {
+        let positions = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(0.0, 1.0),
+            Vec2::new(-1.0, 0.0),
+            Vec2::new(1.0, 0.0),
+        ];
+        // On a sample, only this sample
+        assert_weights(weights_2d(&positions, Vec2::new(0.0, 1.0)), &[0.0, 1.0, 0.0, 0.0]);
+        // Halfway between two samples
+        assert_weights(weights_2d(&positions, Vec2::new(0.0, 0.5)), &[0.5, 0.5, 0.0, 0.0]);
+
+        let weights = weights_2d(&positions, Vec2::new(0.3, 0.4));
+        assert!((weights.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert_eq!(weights[2], 0.0);
+    }
+
+    #[test]
+    fn test_duration_phase_sync() {
+        let samples = vec![
+            BlendSample {
+                animation: 0,
+                duration: 1.0,
+                position: Vec2::new(0.0, 0.0),
+            },
+            BlendSample {
+                animation: 1,
+                duration: 2.0,
+                position: Vec2::new(1.0, 0.0),
+            },
+        ];
+        let mut blend_space = BlendSpace::new("walk".to_string(), samples, false);
+        assert_eq!(blend_space.duration(), 1.0);
+        blend_space.set_position(Vec2::new(0.5, 0.0));
+        assert_eq!(blend_space.duration(), 1.5);
+    }
+}
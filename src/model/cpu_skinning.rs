@@ -0,0 +1,217 @@
+use crate::model::nodes_tree::DualQuatJoint;
+use crate::vertex::{MorphDelta, SkinnedVertex, Vertex, MAX_JOINT_INFLUENCES};
+use glam::{Mat3, Mat4, Quat, Vec3};
+
+/// Skin the vertices on the CPU with the linear blend of the joint matrices, as `shader.wgsl` does. The morph targets
+/// are applied first with their current weights.
+pub fn skin_linear(
+    vertices: &[Vertex], morph_deltas: &[MorphDelta], morph_weights: &[f32], joints: &[Mat4],
+) -> Vec<SkinnedVertex> {
+    vertices
+        .iter()
+        .map(|vertex| {
+            let skin = (0..MAX_JOINT_INFLUENCES).fold(Mat4::ZERO, |skin, i| {
+                skin + joints[vertex.affected_joints[i] as usize] * vertex.joints_weights[i]
+            });
+            skin_vertex(vertex, morph_deltas, morph_weights, skin)
+        })
+        .collect()
+}
+
+/// Skin the vertices on the CPU with the blend of the dual quaternions of the joints and the linear blend of their
+/// scales, as `shader_dq.wgsl` does
+pub fn skin_dual_quat(
+    vertices: &[Vertex], morph_deltas: &[MorphDelta], morph_weights: &[f32], joints: &[DualQuatJoint],
+) -> Vec<SkinnedVertex> {
+    vertices
+        .iter()
+        .map(|vertex| {
+            let skin = blend_dual_quat(joints, vertex.affected_joints, vertex.joints_weights);
+            skin_vertex(vertex, morph_deltas, morph_weights, skin)
+        })
+        .collect()
+}
+
+fn blend_dual_quat(
+    joints: &[DualQuatJoint], affected_joints: [u32; MAX_JOINT_INFLUENCES], weights: [f32; MAX_JOINT_INFLUENCES],
+) -> Mat4 {
+    let first = joints[affected_joints[0] as usize].real;
+    let mut real = Quat::from_xyzw(0.0, 0.0, 0.0, 0.0);
+    let mut dual = Quat::from_xyzw(0.0, 0.0, 0.0, 0.0);
+    let mut scale = Mat3::ZERO;
+    for (joint, weight) in affected_joints.iter().zip(weights) {
+        let joint = &joints[*joint as usize];
+        // Take the shortest path from the first joint
+        let signed_weight = if joint.real.dot(first) < 0.0 { -weight } else { weight };
+        real = real + joint.real * signed_weight;
+        dual = dual + joint.dual * signed_weight;
+        scale += joint.scale * weight;
+    }
+    let norm = real.length();
+    DualQuatJoint {
+        real: real / norm,
+        dual: dual / norm,
+        scale,
+    }
+    .to_mat4()
+}
+
+fn skin_vertex(vertex: &Vertex, morph_deltas: &[MorphDelta], morph_weights: &[f32], skin: Mat4) -> SkinnedVertex {
+    let mut position = Vec3::from(vertex.position);
+    let mut normal = Vec3::from(vertex.normal);
+    let [first_delta, first_weight, count] = vertex.morph_targets.map(|i| i as usize);
+    for i in 0..count {
+        let weight = morph_weights[first_weight + i];
+        let delta = &morph_deltas[first_delta + i];
+        position += weight * Vec3::from_slice(&delta.position[..3]);
+        normal += weight * Vec3::from_slice(&delta.normal[..3]);
+    }
+
+    let normal_matrix = inverse_transpose(Mat3::from_mat4(skin));
+    SkinnedVertex {
+        position: skin.transform_point3(position).to_array(),
+        normal: (normal_matrix * normal).normalize_or_zero().to_array(),
+        uv: vertex.uv,
+    }
+}
+
+/// Inverse transpose of `m` up to a positive scale, from its cofactor matrix. It mirrors `inverse_transpose` of
+/// `common.wgsl` so the CPU and GPU normals match.
+fn inverse_transpose(m: Mat3) -> Mat3 {
+    let cofactor = Mat3::from_cols(
+        m.y_axis.cross(m.z_axis),
+        m.z_axis.cross(m.x_axis),
+        m.x_axis.cross(m.y_axis),
+    );
+    let determinant = m.x_axis.dot(cofactor.x_axis);
+    // A joint scaled to zero has no inverse, the normals are transformed like the positions
+    if determinant == 0.0 {
+        return m;
+    }
+    cofactor * determinant.signum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn vertex(position: Vec3, normal: Vec3, affected_joints: [u32; 4], joints_weights: [f32; 4]) -> Vertex {
+        let mut vertex = Vertex {
+            position: position.to_array(),
+            normal: normal.to_array(),
+            uv: [0.25, 0.75],
+            affected_joints: [0; MAX_JOINT_INFLUENCES],
+            joints_weights: [0.0; MAX_JOINT_INFLUENCES],
+            morph_targets: [0, 0, 0],
+        };
+        vertex.affected_joints[..4].copy_from_slice(&affected_joints);
+        vertex.joints_weights[..4].copy_from_slice(&joints_weights);
+        vertex
+    }
+
+    #[test]
+    fn test_skin_linear() {
+        let joints = [
+            Mat4::IDENTITY,
+            Mat4::from_rotation_translation(Quat::from_rotation_z(FRAC_PI_2), Vec3::new(0.0, 2.0, 0.0)),
+        ];
+        let vertices = [
+            vertex(Vec3::X, Vec3::Y, [1, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]),
+            vertex(Vec3::X, Vec3::Y, [0, 1, 0, 0], [0.5, 0.5, 0.0, 0.0]),
+        ];
+        let skinned = skin_linear(&vertices, &[], &[], &joints);
+        assert!(Vec3::from(skinned[0].position).abs_diff_eq(Vec3::new(0.0, 3.0, 0.0), 1e-6));
+        assert!(Vec3::from(skinned[0].normal).abs_diff_eq(Vec3::NEG_X, 1e-6));
+        assert_eq!(skinned[0].uv, [0.25, 0.75]);
+        // Half way the linear blend goes through the middle
+        assert!(Vec3::from(skinned[1].position).abs_diff_eq(Vec3::new(0.5, 1.5, 0.0), 1e-6));
+    }
+
+    #[test]
+    fn test_skin_linear_non_uniform_scale() {
+        // Squashed along y, the normal of a slope gets steeper instead of following the surface
+        let joints = [Mat4::from_scale(Vec3::new(1.0, 0.5, 1.0))];
+        let normal = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let skinned = skin_linear(
+            &[vertex(Vec3::ZERO, normal, [0; 4], [1.0, 0.0, 0.0, 0.0])],
+            &[],
+            &[],
+            &joints,
+        );
+        assert!(Vec3::from(skinned[0].normal).abs_diff_eq(Vec3::new(1.0, 2.0, 0.0).normalize(), 1e-6));
+    }
+
+    #[test]
+    fn test_skin_linear_zero_scale() {
+        // Flattened along x, the normal keeps the part in the plane instead of vanishing
+        let joints = [Mat4::from_scale(Vec3::new(0.0, 1.0, 1.0))];
+        let normal = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let vertices = [
+            vertex(Vec3::ONE, normal, [0; 4], [1.0, 0.0, 0.0, 0.0]),
+            vertex(Vec3::ONE, Vec3::X, [0; 4], [1.0, 0.0, 0.0, 0.0]),
+        ];
+        let skinned = skin_linear(&vertices, &[], &[], &joints);
+        assert!(Vec3::from(skinned[0].position).abs_diff_eq(Vec3::new(0.0, 1.0, 1.0), 1e-6));
+        assert!(Vec3::from(skinned[0].normal).abs_diff_eq(Vec3::Y, 1e-6));
+        assert_eq!(skinned[1].normal, [0.0; 3]);
+    }
+
+    #[test]
+    fn test_skin_dual_quat() {
+        let matrices = [
+            Mat4::IDENTITY,
+            Mat4::from_scale_rotation_translation(
+                Vec3::new(2.0, 1.0, 1.0),
+                Quat::from_rotation_z(FRAC_PI_2),
+                Vec3::new(0.0, 2.0, 0.0),
+            ),
+        ];
+        let joints = matrices.map(DualQuatJoint::from_mat4);
+        let vertices = [
+            vertex(Vec3::X, Vec3::Y, [1, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]),
+            vertex(Vec3::X, Vec3::Y, [0, 1, 0, 0], [0.5, 0.5, 0.0, 0.0]),
+        ];
+
+        // A single joint gives the same result as the linear blend, scale included
+        let dual_quat = skin_dual_quat(&vertices, &[], &[], &joints);
+        let linear = skin_linear(&vertices, &[], &[], &matrices);
+        assert!(Vec3::from(dual_quat[0].position).abs_diff_eq(Vec3::from(linear[0].position), 1e-5));
+        assert!(Vec3::from(dual_quat[0].normal).abs_diff_eq(Vec3::from(linear[0].normal), 1e-5));
+        // Half way it turns by half around the center of the motion instead of cutting through, with the blended scale
+        let center = Vec3::new(-1.0, 1.0, 0.0);
+        let expected = center + Quat::from_rotation_z(FRAC_PI_2 / 2.0) * (Vec3::new(1.5, 0.0, 0.0) - center);
+        assert!(Vec3::from(dual_quat[1].position).abs_diff_eq(expected, 1e-5));
+    }
+
+    #[test]
+    fn test_eight_influences() {
+        let joints: Vec<Mat4> = (0..8)
+            .map(|i| Mat4::from_translation(Vec3::new(i as f32, 0.0, 0.0)))
+            .collect();
+        let mut spread = vertex(Vec3::ZERO, Vec3::Y, [0, 1, 2, 3], [0.125; 4]);
+        spread.affected_joints[4..].copy_from_slice(&[4, 5, 6, 7]);
+        spread.joints_weights[4..].copy_from_slice(&[0.125; 4]);
+        let linear = skin_linear(&[spread], &[], &[], &joints);
+        assert!(Vec3::from(linear[0].position).abs_diff_eq(Vec3::new(3.5, 0.0, 0.0), 1e-6));
+
+        let joints = joints.into_iter().map(DualQuatJoint::from_mat4).collect::<Vec<_>>();
+        let dual_quat = skin_dual_quat(&[spread], &[], &[], &joints);
+        assert!(Vec3::from(dual_quat[0].position).abs_diff_eq(Vec3::new(3.5, 0.0, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn test_morph_targets() {
+        let mut morphed = vertex(Vec3::ZERO, Vec3::Y, [0; 4], [1.0, 0.0, 0.0, 0.0]);
+        morphed.morph_targets = [1, 0, 1];
+        let deltas = [
+            MorphDelta::default(),
+            MorphDelta {
+                position: [2.0, 0.0, 0.0, 0.0],
+                normal: [0.0, 0.0, 0.0, 0.0],
+            },
+        ];
+        let skinned = skin_linear(&[morphed], &deltas, &[0.5], &[Mat4::IDENTITY]);
+        assert_eq!(skinned[0].position, [1.0, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::ground::{Ground, GroundHit};
+use crate::model::ik::{global_position, rotate_global, solve_two_bone};
+use crate::model::nodes_tree::NodeTree;
+use glam::{Mat4, Quat, Vec3};
+
+/// Highest step a foot goes up or down to reach the ground, in world space
+const MAX_STEP: f32 = 0.5;
+
+/// Plant the feet of `legs`, each an upper leg, a lower leg and a foot, on the world space `ground`. The feet keep
+/// their animated height above the floor of the model, the pelvis goes down or up by the smallest step of the legs
+/// and the feet turn to follow the surface. Return the contacts found, in world space.
+pub fn solve_foot_ik(
+    tree: &mut NodeTree, legs: &[[usize; 3]], pelvis: usize, ground: &Ground, model_matrix: Mat4,
+) -> Vec<GroundHit> {
+    let inverse = model_matrix.inverse();
+    let floor = model_matrix.transform_point3(Vec3::ZERO).y;
+
+    // Targets before any change to the pose
+    let mut contacts = Vec::new();
+    let mut plants = Vec::new();
+    for &[upper, lower, foot] in legs {
+        let position = model_matrix.transform_point3(global_position(tree, foot));
+        let Some(hit) = ground.cast_down(position + Vec3::Y * MAX_STEP, 2.0 * MAX_STEP) else {
+            continue;
+        };
+        let ankle_height = (position.y - floor).max(0.0);
+        let target = hit.position + hit.normal * ankle_height;
+        let rotation = tree.get_global_transform(foot).to_scale_rotation_translation().1;
+        contacts.push(hit);
+        plants.push(([upper, lower, foot], position, target, hit.normal, rotation));
+    }
+    if plants.is_empty() {
+        return contacts;
+    }
+
+    // The pelvis follows the leg going the lowest, the others bend to reach their target
+    let offset = plants
+        .iter()
+        .map(|(_, position, target, _, _)| target.y - position.y)
+        .fold(f32::INFINITY, f32::min);
+    let parent_inverse = match tree.parent(pelvis) {
+        Some(parent) => tree.get_global_transform(parent).inverse(),
+        None => Mat4::IDENTITY,
+    };
+    tree.nodes[pelvis].translate += parent_inverse.transform_vector3(inverse.transform_vector3(Vec3::Y * offset));
+
+    let up = inverse.transform_vector3(Vec3::Y).normalize();
+    for (leg, _, target, normal, rotation) in plants {
+        solve_two_bone(tree, &leg, inverse.transform_point3(target), None, 0.0);
+
+        // Tilt the animated foot from the up axis to the normal
+        let foot = leg[2];
+        let normal = inverse.transform_vector3(normal).normalize();
+        let current = tree.get_global_transform(foot).to_scale_rotation_translation().1;
+        let aligned = Quat::from_rotation_arc(up, normal) * rotation;
+        rotate_global(tree, foot, aligned * current.inverse());
+    }
+    contacts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ground::Heightfield;
+    use crate::model::nodes_tree::test_utils::create_tree;
+    use glam::Vec2;
+
+    #[test]
+    fn test_foot_ik_slope() {
+        // Pelvis one unit above the floor and two legs slightly bent forward, with the feet on the floor
+        let mut tree = create_tree(&[
+            (None, Vec3::new(0.0, 1.0, 0.0)),
+            (Some(0), Vec3::new(-0.2, 0.0, 0.0)),
+            (Some(1), Vec3::new(0.0, -0.5, 0.05)),
+            (Some(2), Vec3::new(0.0, -0.5, -0.05)),
+            (Some(0), Vec3::new(0.2, 0.0, 0.0)),
+            (Some(4), Vec3::new(0.0, -0.5, 0.05)),
+            (Some(5), Vec3::new(0.0, -0.5, -0.05)),
+        ]);
+        let legs = [[1, 2, 3], [4, 5, 6]];
+        let ground = Ground::Plane {
+            point: Vec3::ZERO,
+            tilt: Vec2::new(0.0, std::f32::consts::FRAC_PI_8),
+        };
+
+        let contacts = solve_foot_ik(&mut tree, &legs, 0, &ground, Mat4::IDENTITY);
+        assert_eq!(contacts.len(), 2);
+        let height = 0.2 * std::f32::consts::FRAC_PI_8.tan();
+        // The pelvis goes down to the lower foot, both feet are on the ground
+        assert!((global_position(&tree, 0).y - (1.0 - height)).abs() < 1e-4);
+        assert!(global_position(&tree, 3).abs_diff_eq(Vec3::new(-0.2, -height, 0.0), 1e-3));
+        assert!(global_position(&tree, 6).abs_diff_eq(Vec3::new(0.2, height, 0.0), 1e-3));
+        // And turned to the slope
+        let (_, rotation, _) = tree.get_global_transform(6).to_scale_rotation_translation();
+        assert!((rotation * Vec3::Y).abs_diff_eq(contacts[1].normal, 1e-4));
+    }
+
+    #[test]
+    fn test_foot_ik_off_ground() {
+        // Pelvis one unit above the floor and two legs slightly bent forward, with the feet on the floor
+        let mut tree = create_tree(&[
+            (None, Vec3::new(0.0, 1.0, 0.0)),
+            (Some(0), Vec3::new(-0.2, 0.0, 0.0)),
+            (Some(1), Vec3::new(0.0, -0.5, 0.05)),
+            (Some(2), Vec3::new(0.0, -0.5, -0.05)),
+            (Some(0), Vec3::new(0.2, 0.0, 0.0)),
+            (Some(4), Vec3::new(0.0, -0.5, 0.05)),
+            (Some(5), Vec3::new(0.0, -0.5, -0.05)),
+        ]);
+        let legs = [[1, 2, 3], [4, 5, 6]];
+        // Model twice as big and two units up, only its right foot is above the heightfield, raised by 0.1
+        let model_matrix = Mat4::from_scale_rotation_translation(Vec3::splat(2.0), Quat::IDENTITY, 2.0 * Vec3::Y);
+        let ground = Ground::Heightfield(Heightfield {
+            origin: Vec3::new(0.1, 2.1, -1.0),
+            cell_size: 2.0,
+            columns: 2,
+            rows: 2,
+            heights: vec![0.0; 4],
+        });
+
+        let contacts = solve_foot_ik(&mut tree, &legs, 0, &ground, model_matrix);
+        assert_eq!(contacts.len(), 1);
+        assert!(contacts[0].position.abs_diff_eq(Vec3::new(0.4, 2.1, 0.0), 1e-5));
+        assert!(global_position(&tree, 0).abs_diff_eq(Vec3::new(0.0, 1.05, 0.0), 1e-4));
+        assert!(global_position(&tree, 6).abs_diff_eq(Vec3::new(0.2, 0.05, 0.0), 1e-3));
+    }
+}
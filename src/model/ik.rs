@@ -0,0 +1,480 @@
+use crate::model::nodes_tree::NodeTree;
+use glam::{Quat, Vec3, Vec4};
+
+/// Limits of an IK solver
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IkSettings {
+    pub iterations: usize,
+    /// Distance from the effector to the target under which the chain is solved
+    pub tolerance: f32,
+}
+
+impl Default for IkSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 10,
+            tolerance: 0.001,
+        }
+    }
+}
+
+/// Algorithm moving a chain toward its target
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum IkSolver {
+    /// Cyclic coordinate descent, each chain on its own
+    #[default]
+    Ccd,
+    /// Forward and backward reaching, the chains sharing nodes are solved together
+    Fabrik,
+    /// Closed form on the last three nodes of the chain, turned toward the pole
+    TwoBone,
+}
+
+impl IkSolver {
+    pub const ALL: [IkSolver; 3] = [IkSolver::Ccd, IkSolver::Fabrik, IkSolver::TwoBone];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            IkSolver::Ccd => "CCD",
+            IkSolver::Fabrik => "FABRIK",
+            IkSolver::TwoBone => "Two bone",
+        }
+    }
+}
+
+/// Chain of nodes, from `root` down to `effector`, moved toward a target in world space
+#[derive(Clone, Debug)]
+pub struct IkChain {
+    pub root: Option<usize>,
+    pub effector: Option<usize>,
+    pub target: Vec3,
+    pub solver: IkSolver,
+    /// Point in world space the middle joint of a two bone chain bends toward, like a knee or an elbow
+    pub pole: Option<Vec3>,
+    /// Part of the length of a two bone chain where it slows down before full extension, 0.0 for a hard limit
+    pub softness: f32,
+    pub enabled: bool,
+    /// Distance left from the effector to the target after the last solve, in model space
+    pub distance: f32,
+}
+
+impl IkChain {
+    pub fn new(root: Option<usize>, effector: Option<usize>, target: Vec3, solver: IkSolver) -> Self {
+        Self {
+            root,
+            effector,
+            target,
+            solver,
+            pole: None,
+            softness: 0.0,
+            enabled: true,
+            distance: 0.0,
+        }
+    }
+}
+
+/// Result of a FABRIK solve
+#[derive(Clone, Debug, Default)]
+pub struct FabrikSolution {
+    /// Distance left from the effector of each chain to its target
+    pub distances: Vec<f32>,
+    /// Bones of the chains at the solved positions, as the positions of their parent and child nodes
+    pub bones: Vec<(Vec3, Vec3)>,
+}
+
+/// Rotate the nodes of `chain`, listed from its root to its effector, so the effector reaches `target` with cyclic
+/// coordinate descent. `target` is in the space of the tree. Return the distance left from the effector to the target.
+pub fn solve_ccd(tree: &mut NodeTree, chain: &[usize], target: Vec3, settings: &IkSettings) -> f32 {
+    let Some((&effector, joints)) = chain.split_last() else {
+        return 0.0;
+    };
+    let mut distance = global_position(tree, effector).distance(target);
+
+    for _ in 0..settings.iterations {
+        if distance <= settings.tolerance {
+            break;
+        }
+        for &joint in joints.iter().rev() {
+            let joint_position = global_position(tree, joint);
+            let to_effector = global_position(tree, effector) - joint_position;
+            let to_target = target - joint_position;
+            if to_effector.length_squared() < f32::EPSILON || to_target.length_squared() < f32::EPSILON {
+                continue;
+            }
+            let rotation = Quat::from_rotation_arc(to_effector.normalize(), to_target.normalize());
+            rotate_global(tree, joint, rotation);
+        }
+        distance = global_position(tree, effector).distance(target);
+    }
+    distance
+}
+
+/// Rotate the last three nodes of `chain`, the upper and lower bones and the effector, so the effector reaches
+/// `target` in closed form. The middle joint bends toward `pole`, or keeps its bend direction without one. Past
+/// `softness` times the length of the chain the effector slows down instead of snapping straight at full extension.
+/// The positions are in the space of the tree. Return the distance left from the effector to the target.
+pub fn solve_two_bone(tree: &mut NodeTree, chain: &[usize], target: Vec3, pole: Option<Vec3>, softness: f32) -> f32 {
+    let [upper, lower, effector] = match chain {
+        [.., upper, lower, effector] => [*upper, *lower, *effector],
+        [.., effector] => return global_position(tree, *effector).distance(target),
+        [] => return 0.0,
+    };
+    let a = global_position(tree, upper);
+    let b = global_position(tree, lower);
+    let c = global_position(tree, effector);
+    let upper_length = a.distance(b);
+    let lower_length = b.distance(c);
+    let length = upper_length + lower_length;
+    if upper_length < f32::EPSILON || lower_length < f32::EPSILON || (target - a).length_squared() < f32::EPSILON {
+        return c.distance(target);
+    }
+
+    let to_target = (target - a).normalize();
+    let target_distance = soft_distance(a.distance(target), length, softness)
+        .clamp((upper_length - lower_length).abs() + 1e-4, length - 1e-4);
+
+    // Bend in the plane the chain is in, the pole turns it afterward
+    let bend = (c - a).cross(b - a);
+    let axis = if bend.length_squared() > f32::EPSILON {
+        bend.normalize()
+    } else {
+        let side = (c - a).cross(pole.map_or(Vec3::ZERO, |pole| pole - a));
+        if side.length_squared() > f32::EPSILON {
+            side.normalize()
+        } else {
+            (c - a).any_orthonormal_vector()
+        }
+    };
+
+    // Angles at the upper and lower joints for the distance to reach, by the law of cosines
+    let angle = |from: Vec3, to: Vec3| from.normalize().dot(to.normalize()).clamp(-1.0, 1.0).acos();
+    let cosine_angle = |adjacent_1: f32, adjacent_2: f32, opposite: f32| {
+        ((adjacent_1 * adjacent_1 + adjacent_2 * adjacent_2 - opposite * opposite) / (2.0 * adjacent_1 * adjacent_2))
+            .clamp(-1.0, 1.0)
+            .acos()
+    };
+    let upper_angle = cosine_angle(upper_length, target_distance, lower_length) - angle(c - a, b - a);
+    let lower_angle = cosine_angle(upper_length, lower_length, target_distance) - angle(a - b, c - b);
+    rotate_global(tree, upper, Quat::from_axis_angle(axis, upper_angle));
+    rotate_global(tree, lower, Quat::from_axis_angle(axis, lower_angle));
+
+    // Aim the chain at the target
+    let c = global_position(tree, effector);
+    if (c - a).length_squared() > f32::EPSILON {
+        rotate_global(tree, upper, Quat::from_rotation_arc((c - a).normalize(), to_target));
+    }
+
+    // Turn the chain around the line to the target so the middle joint faces the pole
+    if let Some(pole) = pole {
+        let b = global_position(tree, lower);
+        let project = |v: Vec3| v - to_target * v.dot(to_target);
+        let (bend_direction, pole_direction) = (project(b - a), project(pole - a));
+        if bend_direction.length_squared() > f32::EPSILON && pole_direction.length_squared() > f32::EPSILON {
+            let twist = Quat::from_rotation_arc(bend_direction.normalize(), pole_direction.normalize());
+            rotate_global(tree, upper, twist);
+        }
+    }
+    global_position(tree, effector).distance(target)
+}
+
+/// Distance reached toward a target at `distance` by a chain of `length`. Past `1.0 - softness` of the length it goes
+/// exponentially slower so it gets to full extension only for a target infinitely far.
+fn soft_distance(distance: f32, length: f32, softness: f32) -> f32 {
+    let soft_length = length * softness.clamp(0.0, 1.0);
+    let hard_length = length - soft_length;
+    if soft_length <= 0.0 || distance <= hard_length {
+        return distance.min(length);
+    }
+    hard_length + soft_length * (1.0 - (-(distance - hard_length) / soft_length).exp())
+}
+
+/// Move the nodes of the chains, each listed from its root to its effector with its target, by forward and backward
+/// reaching on their positions, then turn the positions into rotations. The chains form a tree: a node shared by
+/// several chains is a sub-base placed between the positions its branches pull it to. The roots do not move.
+pub fn solve_fabrik(tree: &mut NodeTree, chains: &[(Vec<usize>, Vec3)], settings: &IkSettings) -> FabrikSolution {
+    // Nodes of all the chains, the parents before their children
+    let mut nodes: Vec<usize> = Vec::new();
+    for (chain, _) in chains {
+        for node in chain {
+            if !nodes.contains(node) {
+                nodes.push(*node);
+            }
+        }
+    }
+    nodes.sort_by_key(|node| depth(tree, *node));
+    let index = |node: usize| nodes.iter().position(|n| *n == node);
+    let parents: Vec<Option<usize>> = nodes.iter().map(|node| tree.parent(*node).and_then(index)).collect();
+    let mut targets = vec![None; nodes.len()];
+    for (chain, target) in chains {
+        if let Some(effector) = chain.last().and_then(|effector| index(*effector)) {
+            targets[effector] = Some(*target);
+        }
+    }
+
+    let start: Vec<Vec3> = nodes.iter().map(|node| global_position(tree, *node)).collect();
+    let lengths: Vec<f32> = (0..nodes.len())
+        .map(|i| parents[i].map_or(0.0, |parent| start[i].distance(start[parent])))
+        .collect();
+    let mut solved = start.clone();
+
+    for _ in 0..settings.iterations {
+        let positions: Vec<Vec3> = nodes.iter().map(|node| global_position(tree, *node)).collect();
+        let reached = (0..nodes.len())
+            .all(|i| targets[i].is_none_or(|target| positions[i].distance(target) <= settings.tolerance));
+        if reached {
+            break;
+        }
+
+        // Forward, from the effectors: each node goes to its target and to its length from its moved children
+        solved = positions.clone();
+        for i in (0..nodes.len()).rev() {
+            let mut sum = Vec3::ZERO;
+            let mut count = 0.0;
+            if let Some(target) = targets[i] {
+                sum += target;
+                count += 1.0;
+            }
+            for child in (i + 1..nodes.len()).filter(|child| parents[*child] == Some(i)) {
+                sum += solved[child] + (positions[i] - solved[child]).normalize_or_zero() * lengths[child];
+                count += 1.0;
+            }
+            if count > 0.0 {
+                solved[i] = sum / count;
+            }
+        }
+        // Backward, from the roots back at their place: each node goes to its length from its parent
+        for i in 0..nodes.len() {
+            solved[i] = match parents[i] {
+                Some(parent) => solved[parent] + (solved[i] - solved[parent]).normalize_or_zero() * lengths[i],
+                None => start[i],
+            };
+        }
+
+        // Turn each node toward the solved positions of its children, with the mean of the rotations for a
+        // sub-base. The next iteration starts from the positions the rotations give.
+        for i in 0..nodes.len() {
+            let position = global_position(tree, nodes[i]);
+            let mut sum = Vec4::ZERO;
+            for child in (i + 1..nodes.len()).filter(|child| parents[*child] == Some(i)) {
+                let current = global_position(tree, nodes[child]) - position;
+                let direction = solved[child] - solved[i];
+                if current.length_squared() < f32::EPSILON || direction.length_squared() < f32::EPSILON {
+                    continue;
+                }
+                let rotation = Vec4::from(Quat::from_rotation_arc(current.normalize(), direction.normalize()));
+                // Same hemisphere so the rotations do not cancel out
+                sum += if sum.dot(rotation) < 0.0 { -rotation } else { rotation };
+            }
+            if sum.length_squared() > f32::EPSILON {
+                rotate_global(tree, nodes[i], Quat::from_vec4(sum).normalize());
+            }
+        }
+    }
+
+    FabrikSolution {
+        distances: chains
+            .iter()
+            .map(|(chain, target)| {
+                chain
+                    .last()
+                    .map_or(0.0, |effector| global_position(tree, *effector).distance(*target))
+            })
+            .collect(),
+        bones: (0..nodes.len())
+            .filter_map(|i| parents[i].map(|parent| (solved[parent], solved[i])))
+            .collect(),
+    }
+}
+
+fn depth(tree: &NodeTree, node: usize) -> usize {
+    let mut depth = 0;
+    let mut current = node;
+    while let Some(parent) = tree.parent(current) {
+        depth += 1;
+        current = parent;
+    }
+    depth
+}
+
+pub fn global_position(tree: &NodeTree, node: usize) -> Vec3 {
+    tree.get_global_transform(node).w_axis.truncate()
+}
+
+/// Apply a rotation given in the space of the tree to a node
+pub fn rotate_global(tree: &mut NodeTree, node: usize, rotation: Quat) {
+    let parent_rotation = match tree.parent(node) {
+        Some(parent) => tree.get_global_transform(parent).to_scale_rotation_translation().1,
+        None => Quat::IDENTITY,
+    };
+    let local = &mut tree.nodes[node].rotate;
+    *local = (parent_rotation.inverse() * rotation * parent_rotation * *local).normalize();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::nodes_tree::test_utils::{create_chain, create_tree};
+
+    #[test]
+    fn test_ccd_reaches_target() {
+        let mut tree = create_chain(4, Vec3::X);
+        let chain = tree.chain(0, 3).unwrap();
+        let target = Vec3::new(1.0, 1.5, 0.5);
+
+        let distance = solve_ccd(&mut tree, &chain, target, &IkSettings::default());
+        assert!(distance <= 0.001, "{}", distance);
+        assert!(global_position(&tree, 3).distance(target) <= 0.001);
+        // Only the rotations change, the bones keep their length
+        assert!((global_position(&tree, 1).length() - 1.0).abs() < 1e-5);
+        assert_eq!(tree.nodes[2].translate, Vec3::X);
+    }
+
+    #[test]
+    fn test_ccd_unreachable_target() {
+        let mut tree = create_chain(3, Vec3::X);
+        let chain = tree.chain(0, 2).unwrap();
+
+        // Out of reach, the chain points toward the target
+        let distance = solve_ccd(&mut tree, &chain, Vec3::new(0.0, 5.0, 0.0), &IkSettings::default());
+        assert!((distance - 3.0).abs() < 0.01, "{}", distance);
+        assert!(global_position(&tree, 2).abs_diff_eq(Vec3::new(0.0, 2.0, 0.0), 0.01));
+    }
+
+    #[test]
+    fn test_ccd_iterations() {
+        let mut tree = create_chain(5, Vec3::Y);
+        let chain = tree.chain(0, 4).unwrap();
+        let target = Vec3::new(2.0, 1.0, -1.0);
+
+        // Each iteration gets closer
+        let settings = IkSettings {
+            iterations: 1,
+            tolerance: 0.0,
+        };
+        let mut previous = f32::MAX;
+        for _ in 0..5 {
+            let distance = solve_ccd(&mut tree, &chain, target, &settings);
+            assert!(distance <= previous);
+            previous = distance;
+        }
+
+        // Nothing changes once the target is reached
+        let mut tree = create_chain(3, Vec3::Y);
+        let chain = tree.chain(1, 2).unwrap();
+        let rotations: Vec<Quat> = tree.nodes.iter().map(|node| node.rotate).collect();
+        assert_eq!(solve_ccd(&mut tree, &chain, Vec3::new(0.0, 2.0, 0.0), &settings), 0.0);
+        assert_eq!(tree.nodes.iter().map(|node| node.rotate).collect::<Vec<_>>(), rotations);
+    }
+
+    #[test]
+    fn test_fabrik_reaches_target() {
+        let mut tree = create_chain(4, Vec3::X);
+        let chain = tree.chain(0, 3).unwrap();
+        let target = Vec3::new(1.0, 1.5, 0.5);
+
+        let solution = solve_fabrik(&mut tree, &[(chain, target)], &IkSettings::default());
+        assert!(solution.distances[0] <= 0.01, "{:?}", solution.distances);
+        assert_eq!(solution.bones.len(), 3);
+        assert!(solution.bones[2].1.distance(target) <= 0.001);
+        // The root does not move and the bones keep their length
+        assert_eq!(solution.bones[0].0, Vec3::ZERO);
+        for (parent, child) in solution.bones {
+            assert!((parent.distance(child) - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_fabrik_sub_base() {
+        // Two arms on a spine:
+        //     3-2-1-4-5
+        //         |
+        //         0
+        let mut tree = create_tree(&[
+            (None, Vec3::ZERO),
+            (Some(0), Vec3::Y),
+            (Some(1), Vec3::NEG_X),
+            (Some(2), Vec3::NEG_X),
+            (Some(1), Vec3::X),
+            (Some(4), Vec3::X),
+        ]);
+        let left = tree.chain(0, 3).unwrap();
+        let right = tree.chain(0, 5).unwrap();
+        // Reachable by leaning the spine only
+        let lean = Quat::from_rotation_z(0.3);
+        let chains = [
+            (left, lean * Vec3::new(-2.0, 1.0, 0.0)),
+            (right, lean * Vec3::new(2.0, 1.0, 0.0)),
+        ];
+        let settings = IkSettings {
+            iterations: 50,
+            tolerance: 0.001,
+        };
+
+        let solution = solve_fabrik(&mut tree, &chains, &settings);
+        // Both hands reach their targets, the spine is shared by the two chains
+        assert!(
+            solution.distances.iter().all(|distance| *distance < 0.01),
+            "{:?}",
+            solution.distances
+        );
+        assert_eq!(solution.bones.len(), 5);
+        assert!(global_position(&tree, 1).abs_diff_eq(lean * Vec3::Y, 0.05));
+    }
+
+    #[test]
+    fn test_two_bone() {
+        let mut tree = create_chain(3, Vec3::X);
+        let chain = tree.chain(0, 2).unwrap();
+        let target = Vec3::new(1.2, 0.5, 0.0);
+        let pole = Vec3::new(0.5, 0.0, 3.0);
+
+        let distance = solve_two_bone(&mut tree, &chain, target, Some(pole), 0.0);
+        assert!(distance < 1e-4, "{}", distance);
+        // The elbow bends toward the pole, in the plane of the root, the target and the pole
+        let elbow = global_position(&tree, 1);
+        assert!((elbow.length() - 1.0).abs() < 1e-5);
+        assert!(elbow.z > 0.0);
+        assert!(elbow.dot(target.cross(pole).normalize()).abs() < 1e-4);
+
+        // Without a pole it keeps bending the way it is
+        let distance = solve_two_bone(&mut tree, &chain, Vec3::new(0.5, 1.0, 0.5), None, 0.0);
+        assert!(distance < 1e-4, "{}", distance);
+        assert!(global_position(&tree, 1).z > 0.0);
+    }
+
+    #[test]
+    fn test_two_bone_soft_limit() {
+        let reach = |distance: f32, softness: f32| {
+            let mut tree = create_chain(3, Vec3::X);
+            let chain = tree.chain(0, 2).unwrap();
+            solve_two_bone(
+                &mut tree,
+                &chain,
+                Vec3::new(0.0, distance, 0.0),
+                Some(Vec3::Z),
+                softness,
+            );
+            global_position(&tree, 2).length()
+        };
+
+        // A hard limit reaches the target then stays at full extension
+        assert!((reach(1.5, 0.0) - 1.5).abs() < 1e-4);
+        assert!((reach(3.0, 0.0) - 2.0).abs() < 1e-3);
+        // A soft one is the same up to where it softens, then goes slower without a jump
+        assert!((reach(1.5, 0.2) - 1.5).abs() < 1e-4);
+        let mut previous = reach(1.6, 0.2);
+        for i in 1..20 {
+            let current = reach(1.6 + i as f32 * 0.1, 0.2);
+            assert!(current > previous && current - previous < 0.1 && current < 2.0);
+            previous = current;
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        let tree = create_chain(4, Vec3::X);
+        assert_eq!(tree.chain(1, 3), Some(vec![1, 2, 3]));
+        assert_eq!(tree.chain(2, 2), Some(vec![2]));
+        assert_eq!(tree.chain(3, 1), None);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::vertex::MAX_JOINT_INFLUENCES;
+
+/// Joint influences of a vertex as stored in `Vertex`, the unused slots have no weight
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Influences {
+    pub joints: [u32; MAX_JOINT_INFLUENCES],
+    pub weights: [f32; MAX_JOINT_INFLUENCES],
+    /// Share of the weight of the vertex lost by keeping only the strongest influences
+    pub dropped_weight: f32,
+}
+
+/// Weight dropped at import from the vertices with more influences than the maximum
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InfluenceReport {
+    pub max_influences: usize,
+    /// Most joints with weight kept on a vertex
+    pub used_influences: usize,
+    pub skinned_vertices: usize,
+    /// Vertices with more influences than the maximum
+    pub truncated_vertices: usize,
+    /// Sum of the weight dropped from the truncated vertices
+    pub dropped_weight: f32,
+    pub max_dropped_weight: f32,
+}
+
+impl InfluenceReport {
+    pub fn new(max_influences: usize) -> Self {
+        Self {
+            max_influences,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, influences: &Influences) {
+        self.skinned_vertices += 1;
+        let used = influences.weights.iter().filter(|weight| **weight > 0.0).count();
+        self.used_influences = self.used_influences.max(used);
+        if influences.dropped_weight > 0.0 {
+            self.truncated_vertices += 1;
+            self.dropped_weight += influences.dropped_weight;
+            self.max_dropped_weight = self.max_dropped_weight.max(influences.dropped_weight);
+        }
+    }
+
+    pub fn merge(&mut self, other: &InfluenceReport) {
+        self.used_influences = self.used_influences.max(other.used_influences);
+        self.skinned_vertices += other.skinned_vertices;
+        self.truncated_vertices += other.truncated_vertices;
+        self.dropped_weight += other.dropped_weight;
+        self.max_dropped_weight = self.max_dropped_weight.max(other.max_dropped_weight);
+    }
+
+    /// Mean dropped weight over the truncated vertices
+    pub fn mean_dropped_weight(&self) -> f32 {
+        match self.truncated_vertices {
+            0 => 0.0,
+            truncated => self.dropped_weight / truncated as f32,
+        }
+    }
+}
+
+/// Keep the `max_influences` strongest of the joint influences of a vertex and renormalize their weights. A vertex
+/// without weight follows entirely its first joint.
+pub fn limit_influences(influences: &[(u32, f32)], max_influences: usize) -> Influences {
+    let max_influences = max_influences.clamp(1, MAX_JOINT_INFLUENCES);
+    let mut sorted: Vec<(u32, f32)> = influences.iter().copied().filter(|(_, weight)| *weight > 0.0).collect();
+    sorted.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let total: f32 = sorted.iter().map(|(_, weight)| weight).sum();
+    let kept = &sorted[..sorted.len().min(max_influences)];
+    let kept_total: f32 = kept.iter().map(|(_, weight)| weight).sum();
+
+    let first_joint = influences.first().map_or(0, |(joint, _)| *joint);
+    let mut result = Influences {
+        joints: [first_joint; MAX_JOINT_INFLUENCES],
+        weights: [0.0; MAX_JOINT_INFLUENCES],
+        dropped_weight: 0.0,
+    };
+    if kept.is_empty() {
+        result.weights[0] = 1.0;
+        return result;
+    }
+    for (i, (joint, weight)) in kept.iter().enumerate() {
+        result.joints[i] = *joint;
+        result.weights[i] = weight / kept_total;
+    }
+    result.dropped_weight = 1.0 - kept_total / total;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_influences() {
+        let influences = [(3, 0.1), (5, 0.4), (7, 0.0), (9, 0.3)];
+        let limited = limit_influences(&influences, 8);
+        assert_eq!(limited.joints[..3], [5, 9, 3]);
+        // Weights that do not sum to one are renormalized
+        assert!((limited.weights.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!((limited.weights[0] - 0.5).abs() < 1e-6);
+        assert_eq!(limited.weights[3..], [0.0; 5]);
+        assert_eq!(limited.dropped_weight, 0.0);
+    }
+
+    #[test]
+    fn test_limit_influences_over_maximum() {
+        let influences: Vec<(u32, f32)> = (0..10).map(|joint| (joint, 0.1)).collect();
+        let limited = limit_influences(&influences, 8);
+        assert!((limited.dropped_weight - 0.2).abs() < 1e-6);
+        assert!(limited.weights.iter().all(|weight| (weight - 0.125).abs() < 1e-6));
+
+        let limited = limit_influences(&influences, 4);
+        assert!((limited.dropped_weight - 0.6).abs() < 1e-6);
+        assert_eq!(limited.weights[4..], [0.0; 4]);
+
+        let mut report = InfluenceReport::new(4);
+        report.add(&limited);
+        report.add(&limit_influences(&[(0, 1.0)], 4));
+        let mut merged = InfluenceReport::new(4);
+        merged.merge(&report);
+        merged.merge(&report);
+        assert_eq!(merged.skinned_vertices, 4);
+        assert_eq!(merged.used_influences, 4);
+        assert_eq!(merged.truncated_vertices, 2);
+        assert!((merged.mean_dropped_weight() - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_limit_influences_without_weight() {
+        let limited = limit_influences(&[(4, 0.0), (2, 0.0)], 8);
+        assert_eq!(limited.joints, [4; 8]);
+        assert_eq!(limited.weights[0], 1.0);
+    }
+}
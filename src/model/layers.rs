@@ -0,0 +1,45 @@
+use crate::model::mask::BoneMask;
+use crate::model::pose::Pose;
+use crate::model::Modelv2;
+use crate::playback::Playhead;
+
+/// Animation added on top of the base pose as its difference from a reference frame
+#[derive(Clone, Debug)]
+pub struct AdditiveLayer {
+    pub animation: usize,
+    /// Time of the animation the difference is computed from
+    pub reference_time: f32,
+    pub weight: f32,
+    pub enabled: bool,
+    pub playhead: Playhead,
+    /// Index of the mask restricting the layer to a part of the skeleton
+    pub mask: Option<usize>,
+}
+
+impl AdditiveLayer {
+    pub fn new(animation: usize) -> Self {
+        Self {
+            animation,
+            reference_time: 0.0,
+            weight: 1.0,
+            enabled: true,
+            playhead: Playhead::new(),
+            mask: None,
+        }
+    }
+
+    /// Add the layer on top of `pose`, restricted to the layer mask found in `masks`
+    pub fn apply(&self, model: &Modelv2, pose: &mut Pose, masks: &[BoneMask]) {
+        if !self.enabled || self.weight == 0.0 {
+            return;
+        }
+        let reference = model.sample_pose(self.animation, self.reference_time);
+        let delta = model
+            .sample_pose(self.animation, self.playhead.time)
+            .difference(&reference);
+        match self.mask.and_then(|mask| masks.get(mask)) {
+            Some(mask) => pose.add_masked(&delta, self.weight, mask),
+            None => pose.add(&delta, self.weight),
+        }
+    }
+}
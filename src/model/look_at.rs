@@ -0,0 +1,157 @@
+use crate::model::ik::{global_position, rotate_global};
+use crate::model::nodes_tree::NodeTree;
+use glam::{Quat, Vec3};
+
+/// Joint turned by a look-at constraint
+#[derive(Clone, Debug)]
+pub struct LookAtJoint {
+    pub node: Option<usize>,
+    /// Part of the rotation left to reach the target taken by this joint
+    pub weight: f32,
+    /// Largest rotation of the joint away from its animated pose, in radians
+    pub limit: f32,
+}
+
+impl LookAtJoint {
+    pub fn new(node: Option<usize>, weight: f32, limit: f32) -> Self {
+        Self { node, weight, limit }
+    }
+}
+
+/// Constraint turning joints, from the first to the last, so the forward axis of the last one points at a target
+#[derive(Clone, Debug)]
+pub struct LookAt {
+    pub joints: Vec<LookAtJoint>,
+    /// Axis pointing forward in the local space of the last joint
+    pub forward: Vec3,
+    /// World space point to look at
+    pub target: Vec3,
+    /// Look at the camera instead of `target`
+    pub follow_camera: bool,
+    /// Time for the look to get most of the way to a new target, in seconds, 0.0 to follow it right away
+    pub smoothing: f32,
+    pub enabled: bool,
+    /// Point looked at, catching up with `target`
+    pub smoothed_target: Vec3,
+    /// Angle left between the forward axis and the target after the last solve, in radians
+    pub angle: f32,
+}
+
+impl LookAt {
+    pub fn new(joints: Vec<LookAtJoint>, forward: Vec3, target: Vec3) -> Self {
+        Self {
+            joints,
+            forward,
+            target,
+            follow_camera: false,
+            smoothing: 0.2,
+            enabled: true,
+            smoothed_target: target,
+            angle: 0.0,
+        }
+    }
+
+    /// Move the smoothed target toward the target over `dt` seconds
+    pub fn update(&mut self, dt: f32) {
+        let factor = if self.smoothing > 0.0 {
+            1.0 - (-dt / self.smoothing).exp()
+        } else {
+            1.0
+        };
+        self.smoothed_target = self.smoothed_target.lerp(self.target, factor);
+    }
+}
+
+/// Turn `joints`, given as node, weight and limit, one after the other so the `forward` axis of the last one points
+/// at `target`. Each joint takes its weight of the rotation left, clamped to its limit. The target is in the space of
+/// the tree. Return the angle left to the target.
+pub fn solve_look_at(tree: &mut NodeTree, joints: &[(usize, f32, f32)], forward: Vec3, target: Vec3) -> f32 {
+    let Some(&(last, _, _)) = joints.last() else {
+        return 0.0;
+    };
+    let forward = forward.normalize_or(Vec3::Z);
+    let aim = |tree: &NodeTree| {
+        let (_, rotation, _) = tree.get_global_transform(last).to_scale_rotation_translation();
+        let direction = (target - global_position(tree, last)).normalize_or_zero();
+        (rotation * forward, direction)
+    };
+
+    for &(node, weight, limit) in joints {
+        let (current, direction) = aim(tree);
+        if direction == Vec3::ZERO {
+            return 0.0;
+        }
+        let rotation = Quat::IDENTITY.slerp(Quat::from_rotation_arc(current, direction), weight.clamp(0.0, 1.0));
+        let (axis, angle) = rotation.to_axis_angle();
+        let rotation = if angle > limit {
+            Quat::from_axis_angle(axis, limit.max(0.0))
+        } else {
+            rotation
+        };
+        rotate_global(tree, node, rotation);
+    }
+    let (current, direction) = aim(tree);
+    current.angle_between(direction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::nodes_tree::test_utils::create_tree;
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+    fn head_forward(tree: &NodeTree) -> Vec3 {
+        tree.get_global_transform(2).to_scale_rotation_translation().1 * Vec3::Z
+    }
+
+    #[test]
+    fn test_look_at() {
+        let target = Vec3::new(2.0, 1.5, 0.0);
+        // Spine, neck and head one above the other, looking toward z
+        let mut tree = create_tree(&[
+            (None, Vec3::ZERO),
+            (Some(0), Vec3::new(0.0, 1.0, 0.0)),
+            (Some(1), Vec3::new(0.0, 0.5, 0.0)),
+        ]);
+        let angle = solve_look_at(&mut tree, &[(0, 0.5, PI), (2, 1.0, PI)], Vec3::Z, target);
+        assert!(angle < 1e-4, "{}", angle);
+        assert!(head_forward(&tree).abs_diff_eq(Vec3::X, 1e-4));
+        // The spine took half of the turn, the head the rest
+        let (_, spine, _) = tree.get_global_transform(0).to_scale_rotation_translation();
+        assert!(spine.abs_diff_eq(Quat::from_rotation_y(FRAC_PI_4), 1e-4));
+    }
+
+    #[test]
+    fn test_look_at_limits() {
+        let target = Vec3::new(2.0, 1.5, 0.0);
+        // Spine, neck and head one above the other, looking toward z
+        let neck = [
+            (None, Vec3::ZERO),
+            (Some(0), Vec3::new(0.0, 1.0, 0.0)),
+            (Some(1), Vec3::new(0.0, 0.5, 0.0)),
+        ];
+        let mut tree = create_tree(&neck);
+        let limit = 10.0_f32.to_radians();
+        let angle = solve_look_at(&mut tree, &[(0, 0.5, PI), (2, 1.0, limit)], Vec3::Z, target);
+        assert!((angle - (FRAC_PI_4 - limit)).abs() < 1e-4, "{}", angle);
+
+        // A joint without weight does not turn
+        let mut tree = create_tree(&neck);
+        let angle = solve_look_at(&mut tree, &[(1, 0.0, PI)], Vec3::Z, target);
+        assert!((angle - FRAC_PI_2).abs() < 1e-4);
+        assert!(head_forward(&tree).abs_diff_eq(Vec3::Z, 1e-6));
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut look_at = LookAt::new(Vec::new(), Vec3::Z, Vec3::ZERO);
+        look_at.smoothing = 1.0;
+        look_at.target = Vec3::new(2.0, 0.0, 0.0);
+        look_at.update(2.0_f32.ln());
+        assert!(look_at.smoothed_target.abs_diff_eq(Vec3::new(1.0, 0.0, 0.0), 1e-5));
+
+        look_at.smoothing = 0.0;
+        look_at.update(0.01);
+        assert_eq!(look_at.smoothed_target, look_at.target);
+    }
+}
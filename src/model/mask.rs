@@ -0,0 +1,71 @@
+/// Weight of each node in a blend or a layer, nodes outside the mask keep the pose below
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoneMask {
+    pub name: String,
+    pub weights: Vec<f32>,
+}
+
+impl BoneMask {
+    /// Mask with a weight of 0.0 on every node
+    pub fn new(name: String, node_count: usize) -> Self {
+        Self {
+            name,
+            weights: vec![0.0; node_count],
+        }
+    }
+
+    /// Mask with a weight of 1.0 on `root` and all its descendants
+    pub fn from_subtree(name: String, parents: &[Option<usize>], root: usize) -> Self {
+        let mut mask = Self::new(name, parents.len());
+        mask.set_subtree(parents, root, 1.0);
+        mask
+    }
+
+    /// Set the weight of `root` and all its descendants
+    pub fn set_subtree(&mut self, parents: &[Option<usize>], root: usize, weight: f32) {
+        for node in 0..parents.len() {
+            if is_in_subtree(parents, node, root) {
+                self.weights[node] = weight;
+            }
+        }
+    }
+
+    pub fn weight(&self, node: usize) -> f32 {
+        self.weights.get(node).copied().unwrap_or(0.0)
+    }
+}
+
+fn is_in_subtree(parents: &[Option<usize>], node: usize, root: usize) -> bool {
+    let mut current = Some(node);
+    while let Some(index) = current {
+        if index == root {
+            return true;
+        }
+        current = parents[index];
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_subtree() {
+        //   0
+        //  / \
+        // 1   2
+        //     |
+        //     3
+        let parents = [None, Some(0), Some(0), Some(2)];
+
+        let mask = BoneMask::from_subtree("arm".to_string(), &parents, 2);
+        assert_eq!(mask.weights, vec![0.0, 0.0, 1.0, 1.0]);
+
+        let mut mask = BoneMask::from_subtree("all".to_string(), &parents, 0);
+        assert_eq!(mask.weights, vec![1.0; 4]);
+        mask.set_subtree(&parents, 3, 0.5);
+        assert_eq!(mask.weights, vec![1.0, 1.0, 1.0, 0.5]);
+        assert_eq!(mask.weight(10), 0.0);
+    }
+}
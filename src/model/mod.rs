@@ -1,21 +1,53 @@
-use crate::model::animation::{Animation, ChannelType, NodeChannels};
+use crate::ground::{Ground, GroundHit};
+use crate::model::animation::{Animation, AnimationEvent, ChannelType, NodeChannels};
 use crate::texture::Texture;
-use crate::vertex::Vertex;
+use crate::vertex::{CompactVertex, InfluenceLayout, MorphDelta, SkinnedVertex, Vertex, MAX_JOINT_INFLUENCES};
 use animation::{Channel, InterpolationType};
 use anyhow::{Context, Result};
-use glam::{Mat4, Quat};
-use gltf::animation::util::Rotations;
+use glam::{Mat4, Quat, Vec3};
 use gltf::buffer::Data;
 use gltf::image::Format;
-use gltf::mesh::util::{ReadIndices, ReadJoints, ReadTexCoords, ReadWeights};
+use gltf::mesh::util::{ReadIndices, ReadJoints, ReadWeights};
 use gltf::Document;
-use nodes_tree::{create_nodes_tree_from_joints, NodeTree};
+use influences::limit_influences;
+use log::warn;
+use nodes_tree::{create_nodes_tree, NodeTree};
+use root_motion::RootMotion;
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::Path;
 use wgpu::util::DeviceExt;
-use wgpu::{BindGroup, BindGroupLayout, Device, Queue};
+use wgpu::{BindGroup, BindGroupLayout, Device, IndexFormat, Queue};
 
 mod animation;
+mod blend_space;
+mod cpu_skinning;
+mod foot_ik;
+mod ik;
+mod influences;
+mod layers;
+mod look_at;
+mod mask;
 mod nodes_tree;
+mod pose;
+mod root_motion;
+mod state_machine;
+
+pub use ik::{IkChain, IkSettings, IkSolver};
+pub use influences::InfluenceReport;
+pub use layers::AdditiveLayer;
+pub use look_at::{LookAt, LookAtJoint};
+pub use mask::BoneMask;
+pub use pose::Pose;
+pub use root_motion::{RootMotionMode, RootTransform};
+pub use state_machine::{Animator, Motion, ParameterValue};
+
+/// Extras of a glTF animation
+#[derive(Deserialize, Default)]
+struct AnimationExtras {
+    #[serde(default)]
+    events: Vec<AnimationEvent>,
+}
 
 #[derive(Default)]
 pub struct ImageData {
@@ -34,57 +66,273 @@ impl ImageData {
     }
 }
 
+pub struct Material {
+    pub texture: ImageData,
+    texture_buffer: Option<Texture>,
+}
+
+/// A primitive of a mesh, drawn with its own material
+pub struct Submesh {
+    /// First index of the submesh in the index buffer
+    pub index_start: u32,
+    pub index_count: u32,
+    /// Offset added to the indices, they are local to the submesh vertices
+    pub base_vertex: i32,
+    pub index_format: IndexFormat,
+    pub material: usize,
+}
+
+/// Indices of all the submeshes, each submesh uses the 16 bits buffer when its indices fit in it
+#[derive(Default)]
+pub struct Indices {
+    pub u16: Vec<u16>,
+    pub u32: Vec<u32>,
+}
+
+impl Indices {
+    /// Append the indices in the smallest format able to hold them. Return the format and the first index.
+    pub fn push(&mut self, indices: &[u32]) -> (IndexFormat, u32) {
+        if indices.iter().all(|i| *i <= u16::MAX as u32) {
+            let start = self.u16.len() as u32;
+            self.u16.extend(indices.iter().map(|i| *i as u16));
+            (IndexFormat::Uint16, start)
+        } else {
+            let start = self.u32.len() as u32;
+            self.u32.extend_from_slice(indices);
+            (IndexFormat::Uint32, start)
+        }
+    }
+}
+
+/// The node a primitive is drawn from
+struct MeshInstance {
+    joint_offset: u32,
+    skinned: bool,
+    /// First weight of the node in the morph weights buffer
+    morph_weights_start: u32,
+    /// Joints kept at most per vertex
+    max_influences: usize,
+}
+
 pub struct Modelv2 {
     vertices: Vec<Vertex>,
-    indices: Vec<u16>,
-    texture: ImageData,
+    indices: Indices,
+    submeshes: Vec<Submesh>,
+    materials: Vec<Material>,
+    morph_deltas: Vec<MorphDelta>,
+    /// Nodes with morph targets, their weights are written one after the other in the morph weights buffer
+    morph_nodes: Vec<usize>,
     nodes_tree: NodeTree,
     animations: Vec<Animation>,
+    /// Root motion of each animation, extracted from the root joint
+    root_motions: Vec<RootMotion>,
+    root_joint: Option<usize>,
+    influence_report: InfluenceReport,
 
     vertices_buffer: Option<wgpu::Buffer>,
-    indices_buffer: Option<wgpu::Buffer>,
-    texture_buffer: Option<Texture>,
+    indices_u16_buffer: Option<wgpu::Buffer>,
+    indices_u32_buffer: Option<wgpu::Buffer>,
     joints_buffer: Option<wgpu::Buffer>,
+    morph_deltas_buffer: Option<wgpu::Buffer>,
+    morph_weights_buffer: Option<wgpu::Buffer>,
     joints_bind_group: Option<BindGroup>,
+    /// Output of the compute skinning pass and the bind group of the pass
+    skinned_vertices_buffer: Option<wgpu::Buffer>,
+    skinning_bind_group: Option<BindGroup>,
 }
 
 impl Modelv2 {
-    pub fn load_woman() -> Result<Self> {
-        let model_path = &Path::new("rsc").join("Woman.gltf");
-        let (gltf, buffers, images) = gltf::import(model_path).context("File should be in rsc folder")?;
+    pub fn load(model_path: &Path) -> Result<Self> {
+        Self::load_with_max_influences(model_path, MAX_JOINT_INFLUENCES)
+    }
+
+    /// Load keeping at most `max_influences` joints per vertex, up to `MAX_JOINT_INFLUENCES`
+    pub fn load_with_max_influences(model_path: &Path, max_influences: usize) -> Result<Self> {
+        let gltf =
+            gltf::Gltf::open(model_path).with_context(|| format!("Should be able to open {}", model_path.display()))?;
+        let base = model_path.parent();
+        let buffers = gltf::import_buffers(&gltf.document, base, gltf.blob.clone())
+            .context("Should be able to load the buffers")?;
+        // A missing or unreadable image should not prevent the model from loading
+        let images: Vec<Option<gltf::image::Data>> = gltf
+            .document
+            .images()
+            .map(
+                |image| match gltf::image::Data::from_source(image.source(), base, &buffers) {
+                    Ok(data) => Some(data),
+                    Err(err) => {
+                        warn!("Could not load image {}: {}", image.index(), err);
+                        None
+                    }
+                },
+            )
+            .collect();
+        let gltf = gltf.document;
 
         let scene = gltf
             .default_scene()
             .or_else(|| gltf.scenes().nth(0))
             .context("Should have a scene")?;
-        let mesh_node = scene
-            .nodes()
-            .nth(0)
-            .context("Should have a node")?
-            .children()
-            .nth(0)
-            .context("Should have a child")?;
-
-        let skin = mesh_node.skin().context("Should have a skin")?;
-        let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+
         let nodes = gltf.nodes().collect::<Vec<gltf::Node>>();
-        let inverse_bind_matrices = skin
+        let mut nodes_tree = create_nodes_tree(&nodes);
+
+        let mut vertices = Vec::new();
+        let mut indices = Indices::default();
+        let mut submeshes = Vec::new();
+        let mut materials = Vec::new();
+        let mut morph_deltas = Vec::new();
+        let mut morph_nodes = Vec::new();
+        let mut morph_weights_len = 0;
+        // Index in materials of each glTF material, None is the default material
+        let mut materials_index: HashMap<Option<usize>, usize> = HashMap::new();
+        // A skin can be shared by many nodes, its joints are only added once
+        let mut skins_joint_offset: HashMap<usize, u32> = HashMap::new();
+        let mut influence_report = InfluenceReport::new(max_influences.clamp(1, MAX_JOINT_INFLUENCES));
+
+        let mut to_visit: Vec<gltf::Node> = scene.nodes().collect();
+        while let Some(node) = to_visit.pop() {
+            to_visit.extend(node.children());
+
+            let mesh = match node.mesh() {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+
+            let joint_offset = match node.skin() {
+                Some(skin) => match skins_joint_offset.get(&skin.index()) {
+                    Some(offset) => *offset,
+                    None => {
+                        let offset = Self::load_skin(&skin, &buffers, &mut nodes_tree)?;
+                        skins_joint_offset.insert(skin.index(), offset);
+                        offset
+                    }
+                },
+                // A static mesh follows its node, so the node is added as a joint with no bind offset
+                None => nodes_tree.add_joint(node.index(), Mat4::IDENTITY) as u32,
+            };
+
+            // Every primitive of a mesh has the same number of morph targets
+            let morph_targets_len = mesh.primitives().map(|p| p.morph_targets().len()).max().unwrap_or(0);
+            let morph_weights_start = morph_weights_len;
+            if morph_targets_len > 0 {
+                nodes_tree.nodes[node.index()].weights.resize(morph_targets_len, 0.0);
+                morph_nodes.push(node.index());
+                morph_weights_len += morph_targets_len as u32;
+            }
+
+            let instance = MeshInstance {
+                joint_offset,
+                skinned: node.skin().is_some(),
+                morph_weights_start,
+                max_influences: influence_report.max_influences,
+            };
+
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    warn!(
+                        "Skipping primitive of mesh {}, only triangles are supported",
+                        mesh.index()
+                    );
+                    continue;
+                }
+                let material = primitive.material();
+                let material = match materials_index.get(&material.index()) {
+                    Some(index) => *index,
+                    None => {
+                        materials.push(Material {
+                            texture: Self::load_material_texture(&material, &images)?,
+                            texture_buffer: None,
+                        });
+                        materials_index.insert(material.index(), materials.len() - 1);
+                        materials.len() - 1
+                    }
+                };
+
+                let (submesh, primitive_report) = Self::load_primitive(
+                    &primitive,
+                    &buffers,
+                    &instance,
+                    material,
+                    &mut vertices,
+                    &mut indices,
+                    &mut morph_deltas,
+                )?;
+                submeshes.push(submesh);
+                influence_report.merge(&primitive_report);
+            }
+        }
+
+        if submeshes.is_empty() {
+            return Err(anyhow::anyhow!("Should have at least one mesh in the scene"));
+        }
+
+        if influence_report.truncated_vertices > 0 {
+            warn!(
+                "{} of {} skinned vertices have more than {} joint influences, dropped weight {:.3} on average and {:.3} at most",
+                influence_report.truncated_vertices,
+                influence_report.skinned_vertices,
+                influence_report.max_influences,
+                influence_report.mean_dropped_weight(),
+                influence_report.max_dropped_weight
+            );
+        }
+
+        let mut animations = Self::load_animation(gltf, &buffers, &nodes_tree)?;
+        Self::load_animation_events(model_path, &mut animations)?;
+        let root_joint = nodes_tree.root_joint();
+        let root_motions = Self::load_root_motions(&animations, &nodes_tree, root_joint);
+        Ok(Self {
+            vertices,
+            indices,
+            submeshes,
+            materials,
+            morph_deltas,
+            morph_nodes,
+            nodes_tree,
+            animations,
+            root_motions,
+            root_joint,
+            influence_report,
+            vertices_buffer: None,
+            indices_u16_buffer: None,
+            indices_u32_buffer: None,
+            joints_buffer: None,
+            morph_deltas_buffer: None,
+            morph_weights_buffer: None,
+            joints_bind_group: None,
+            skinned_vertices_buffer: None,
+            skinning_bind_group: None,
+        })
+    }
+
+    /// Add the joints of the skin to the tree and return the index of its first joint
+    fn load_skin(skin: &gltf::Skin, buffers: &[Data], nodes_tree: &mut NodeTree) -> Result<u32> {
+        let inverse_bind_matrices: Vec<Mat4> = match skin
             .reader(|buffer| Some(&buffers[buffer.index()]))
             .read_inverse_bind_matrices()
-            .context("Should have inverse bind matrices")?;
-        let inverse_bind_matrices: Vec<glam::Mat4> = inverse_bind_matrices
-            .map(|m| glam::Mat4::from_cols_array_2d(&m))
-            .collect();
-        let nodes_tree = create_nodes_tree_from_joints(joints, nodes, inverse_bind_matrices);
+        {
+            Some(matrices) => matrices.map(|m| Mat4::from_cols_array_2d(&m)).collect(),
+            None => vec![Mat4::IDENTITY; skin.joints().count()],
+        };
+
+        let offset = nodes_tree.joints_len() as u32;
+        for (joint, inverse_bind_matrix) in skin.joints().zip(inverse_bind_matrices) {
+            nodes_tree.add_joint(joint.index(), inverse_bind_matrix);
+        }
+        Ok(offset)
+    }
 
-        let mesh = mesh_node.mesh().context("Should have a mesh")?;
-        let primitive = mesh.primitives().nth(0).context("Should have a primitive")?;
+    fn load_primitive(
+        primitive: &gltf::Primitive, buffers: &[Data], instance: &MeshInstance, material: usize,
+        vertices: &mut Vec<Vertex>, indices: &mut Indices, morph_deltas: &mut Vec<MorphDelta>,
+    ) -> Result<(Submesh, InfluenceReport)> {
         let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
         let positions: Vec<[f32; 3]> = reader.read_positions().context("Should have positions")?.collect();
         let normals: Vec<[f32; 3]> = reader.read_normals().context("Should have normals")?.collect();
-        let uvs: Vec<[f32; 2]> = match reader.read_tex_coords(0).context("Should have uvs")? {
-            ReadTexCoords::F32(iter) => iter.collect(),
-            _ => return Err(anyhow::anyhow!("UVs should be f32")),
+        let uvs: Vec<[f32; 2]> = match reader.read_tex_coords(0) {
+            Some(uvs) => uvs.into_f32().collect(),
+            None => vec![[0.0, 0.0]; positions.len()],
         };
 
         if positions.len() != normals.len() || positions.len() != uvs.len() {
@@ -93,103 +341,164 @@ impl Modelv2 {
             ));
         }
 
-        let affected_joints: Option<Vec<[u32; 4]>> = {
-            let affected_joints = reader.read_joints(0);
-            if let Some(affected_joints) = affected_joints {
-                match affected_joints {
-                    ReadJoints::U8(joints) => Some(joints.map(|j: [u8; 4]| j.map(|i| i as u32)).collect()),
-                    ReadJoints::U16(joints) => Some(joints.map(|j: [u16; 4]| j.map(|i| i as u32)).collect()),
-                }
-            } else {
-                None
+        // Each set of JOINTS_n and WEIGHTS_n adds 4 influences to the vertices
+        let mut influences: Vec<Vec<(u32, f32)>> = vec![Vec::new(); positions.len()];
+        let mut set = 0;
+        while let (Some(joints), Some(weights)) = (reader.read_joints(set), reader.read_weights(set)) {
+            let affected_joints: Vec<[u32; 4]> = match joints {
+                ReadJoints::U8(joints) => joints.map(|j: [u8; 4]| j.map(|i| i as u32)).collect(),
+                ReadJoints::U16(joints) => joints.map(|j: [u16; 4]| j.map(|i| i as u32)).collect(),
+            };
+            let joints_weights: Vec<[f32; 4]> = match weights {
+                ReadWeights::U8(weight) => weight.map(|w: [u8; 4]| w.map(|i| (i as f32) / 255.0)).collect(),
+                ReadWeights::U16(weight) => weight.map(|w: [u16; 4]| w.map(|i| (i as f32) / 65535.0)).collect(),
+                ReadWeights::F32(weight) => weight.collect(),
+            };
+            for ((influences, joints), weights) in influences.iter_mut().zip(affected_joints).zip(joints_weights) {
+                influences.extend(joints.into_iter().zip(weights));
             }
-        };
+            set += 1;
+        }
 
-        let joints_weights: Option<Vec<[f32; 4]>> = {
-            let joints_weights = reader.read_weights(0);
-            if let Some(joints_weights) = joints_weights {
-                match joints_weights {
-                    ReadWeights::U8(weight) => Some(weight.map(|w: [u8; 4]| w.map(|i| (i as f32) / 255.0)).collect()),
-                    ReadWeights::U16(weight) => {
-                        Some(weight.map(|w: [u16; 4]| w.map(|i| (i as f32) / 65535.0)).collect())
-                    }
-                    ReadWeights::F32(weight) => Some(weight.collect()),
-                }
-            } else {
-                None
-            }
-        };
+        // Missing attributes of a target do not move the vertex
+        let mut morph_positions: Vec<Vec<[f32; 3]>> = Vec::new();
+        let mut morph_normals: Vec<Vec<[f32; 3]>> = Vec::new();
+        for (positions_delta, normals_delta, _) in reader.read_morph_targets() {
+            morph_positions.push(match positions_delta {
+                Some(deltas) => deltas.collect(),
+                None => vec![[0.0; 3]; positions.len()],
+            });
+            morph_normals.push(match normals_delta {
+                Some(deltas) => deltas.collect(),
+                None => vec![[0.0; 3]; positions.len()],
+            });
+        }
 
-        let mut vertices = Vec::with_capacity(positions.len());
+        let mut influence_report = InfluenceReport::new(instance.max_influences);
+        let base_vertex = vertices.len();
         for i in 0..positions.len() {
-            let affected_joints = match &affected_joints {
-                Some(affected_joints) => affected_joints[i],
-                None => [0, 0, 0, 0],
-            };
-            let joints_weights = match &joints_weights {
-                Some(joints_weights) => joints_weights[i],
-                None => [0.0, 0.0, 0.0, 0.0],
+            // Without skinning data, the vertex follow entirely the first joint
+            let joint_offset = instance.joint_offset;
+            let influences = if instance.skinned && !influences[i].is_empty() {
+                let skin_influences: Vec<(u32, f32)> =
+                    influences[i].iter().map(|(joint, weight)| (joint + joint_offset, *weight)).collect();
+                let skin_influences = limit_influences(&skin_influences, instance.max_influences);
+                influence_report.add(&skin_influences);
+                skin_influences
+            } else {
+                limit_influences(&[(joint_offset, 1.0)], 1)
             };
+
+            // The deltas of a vertex are next to each other
+            let morph_start = morph_deltas.len() as u32;
+            for (positions_delta, normals_delta) in morph_positions.iter().zip(morph_normals.iter()) {
+                let [px, py, pz] = positions_delta[i];
+                let [nx, ny, nz] = normals_delta[i];
+                morph_deltas.push(MorphDelta {
+                    position: [px, py, pz, 0.0],
+                    normal: [nx, ny, nz, 0.0],
+                });
+            }
+
             vertices.push(Vertex {
                 position: positions[i],
                 normal: normals[i],
                 uv: uvs[i],
-                affected_joints,
-                joints_weights,
+                affected_joints: influences.joints,
+                joints_weights: influences.weights,
+                morph_targets: [morph_start, instance.morph_weights_start, morph_positions.len() as u32],
             });
         }
 
-        let indices: Vec<u16> = match reader.read_indices().context("Should have indices")? {
-            ReadIndices::U8(iter) => iter.map(|i| i as u16).collect(),
-            ReadIndices::U16(iter) => iter.collect(),
-            _ => {
-                return Err(anyhow::anyhow!("Indices should be u8 or u16"));
-            }
+        let primitive_indices: Vec<u32> = match reader.read_indices() {
+            Some(ReadIndices::U8(iter)) => iter.map(|i| i as u32).collect(),
+            Some(ReadIndices::U16(iter)) => iter.map(|i| i as u32).collect(),
+            Some(ReadIndices::U32(iter)) => iter.collect(),
+            None => (0..positions.len() as u32).collect(),
         };
+        let (index_format, index_start) = indices.push(&primitive_indices);
+
+        let submesh = Submesh {
+            index_start,
+            index_count: primitive_indices.len() as u32,
+            base_vertex: base_vertex as i32,
+            index_format,
+            material,
+        };
+        Ok((submesh, influence_report))
+    }
 
-        let material = primitive.material();
-        let image = material
-            .pbr_metallic_roughness()
+    fn load_material_texture(material: &gltf::Material, images: &[Option<gltf::image::Data>]) -> Result<ImageData> {
+        let pbr = material.pbr_metallic_roughness();
+        let image_data = pbr
             .base_color_texture()
-            .context("Should have a base color texture")?
-            .texture()
-            .source();
-        let image_data = &images[image.index()];
+            .and_then(|info| images[info.texture().source().index()].as_ref());
+        let image_data = match image_data {
+            Some(image_data) => image_data,
+            None => {
+                // No texture, a single pixel of the base color is used instead. The factor is linear and the
+                // texture is sampled as sRGB.
+                let color = pbr.base_color_factor().map(|c| (c.powf(1.0 / 2.2) * 255.0) as u8);
+                return Ok(ImageData::new(color.to_vec(), 1, 1));
+            }
+        };
+
         let image_rgba: Vec<u8> = match image_data.format {
             Format::R8G8B8 => image_data
                 .pixels
                 .chunks_exact(3)
-                .map(|chunk| {
-                    let mut chunk = chunk.to_vec();
-                    chunk.push(255);
-                    chunk
-                })
-                .flatten()
+                .flat_map(|chunk| [chunk[0], chunk[1], chunk[2], 255])
                 .collect(),
             Format::R8G8B8A8 => image_data.pixels.clone(),
             _ => {
-                return Err(anyhow::anyhow!("Image format should be R8G8B8"));
+                return Err(anyhow::anyhow!("Image format should be R8G8B8 or R8G8B8A8"));
             }
         };
 
-        let texture = ImageData::new(image_rgba, image_data.width, image_data.height);
+        Ok(ImageData::new(image_rgba, image_data.width, image_data.height))
+    }
 
-        let animations = Self::load_animation(gltf, &buffers, &nodes_tree)?;
-        Ok(Self {
-            vertices,
-            indices,
-            texture,
-            nodes_tree,
-            animations,
-            vertices_buffer: None,
-            indices_buffer: None,
-            texture_buffer: None,
-            joints_buffer: None,
-            joints_bind_group: None,
-        })
+    /// Root transform at the start of each animation, root motion is off until a mode is set
+    fn load_root_motions(animations: &[Animation], nodes_tree: &NodeTree, root_joint: Option<usize>) -> Vec<RootMotion> {
+        let parents = nodes_tree.parents();
+        animations
+            .iter()
+            .map(|animation| {
+                let start = root_joint
+                    .map(|root| {
+                        let mut pose = nodes_tree.bind_pose().clone();
+                        animation.sample(0.0, &mut pose);
+                        RootTransform::from_pose(&pose, parents, root)
+                    })
+                    .unwrap_or_default();
+                RootMotion {
+                    mode: RootMotionMode::Off,
+                    start,
+                }
+            })
+            .collect()
     }
 
-    fn load_animation(gltf: Document, buffers: &Vec<Data>, nodes_tree: &NodeTree) -> Result<Vec<Animation>> {
+    /// Add the events of the `.events.json` file next to the model, it maps animation names to their events
+    fn load_animation_events(model_path: &Path, animations: &mut [Animation]) -> Result<()> {
+        let events_path = model_path.with_extension("events.json");
+        if !events_path.exists() {
+            return Ok(());
+        }
+        let file = std::fs::read_to_string(&events_path)
+            .with_context(|| format!("Should be able to read {}", events_path.display()))?;
+        let events: HashMap<String, Vec<AnimationEvent>> = serde_json::from_str(&file)
+            .with_context(|| format!("Should be a valid events file {}", events_path.display()))?;
+        for (name, events) in events {
+            match animations.iter_mut().find(|animation| animation.name == name) {
+                Some(animation) => animation.add_events(events),
+                None => warn!("No animation {} for the events of {}", name, events_path.display()),
+            }
+        }
+        Ok(())
+    }
+
+    fn load_animation(gltf: Document, buffers: &[Data], nodes_tree: &NodeTree) -> Result<Vec<Animation>> {
         let mut animations = Vec::new();
         for animation in gltf.animations() {
             let name = animation.name().unwrap_or("No name").to_string();
@@ -199,6 +508,10 @@ impl Modelv2 {
                 let node_id = channel.target().node().index();
                 let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
                 let times: Vec<f32> = reader.read_inputs().context("Should have input")?.collect();
+                if times.is_empty() {
+                    warn!("Skipping channel of node {} without keyframes in animation {}", node_id, name);
+                    continue;
+                }
                 let values = reader.read_outputs().context("Should have output")?;
                 let interpolation = match channel.sampler().interpolation() {
                     gltf::animation::Interpolation::Linear => InterpolationType::LINEAR,
@@ -211,79 +524,119 @@ impl Modelv2 {
                         if channels[node_id].is_none() {
                             channels[node_id] = Some(NodeChannels::default());
                         }
-                        channels[node_id].as_mut().unwrap().translation = Some(Channel {
+                        channels[node_id].as_mut().unwrap().translation = Some(Channel::new(
                             interpolation,
                             times,
-                            values: ChannelType::Translation(translations),
-                        });
+                            ChannelType::Translation(translations),
+                        ));
                     }
                     gltf::animation::util::ReadOutputs::Rotations(rotations) => {
-                        let rotations: Vec<Quat> = match rotations {
-                            Rotations::F32(iter) => iter
-                                .into_iter()
-                                .map(|v| Quat::from_array(v))
-                                .map(|q| q.normalize())
-                                .collect(),
-                            _ => unimplemented!("Rotations should be f32"),
-                        };
+                        // The tangents of a cubic spline are not unit quaternions, only its values are normalized
+                        let cubic_spline = matches!(interpolation, InterpolationType::CUBICSPLINE);
+                        let rotations: Vec<Quat> = rotations
+                            .into_f32()
+                            .enumerate()
+                            .map(|(i, v)| {
+                                let q = Quat::from_array(v);
+                                if cubic_spline && i % 3 != 1 {
+                                    q
+                                } else {
+                                    q.normalize()
+                                }
+                            })
+                            .collect();
                         if channels[node_id].is_none() {
                             channels[node_id] = Some(NodeChannels::default());
                         }
-                        channels[node_id].as_mut().unwrap().rotation = Some(Channel {
-                            interpolation,
-                            times,
-                            values: ChannelType::Rotation(rotations),
-                        });
+                        channels[node_id].as_mut().unwrap().rotation =
+                            Some(Channel::new(interpolation, times, ChannelType::Rotation(rotations)));
                     }
                     gltf::animation::util::ReadOutputs::Scales(iter) => {
                         let scales: Vec<glam::Vec3> = iter.into_iter().map(|v| glam::Vec3::from(v)).collect();
                         if channels[node_id].is_none() {
                             channels[node_id] = Some(NodeChannels::default());
                         }
-                        channels[node_id].as_mut().unwrap().scale = Some(Channel {
-                            interpolation,
-                            times,
-                            values: ChannelType::Scale(scales),
-                        });
+                        channels[node_id].as_mut().unwrap().scale =
+                            Some(Channel::new(interpolation, times, ChannelType::Scale(scales)));
                     }
-                    gltf::animation::util::ReadOutputs::MorphTargetWeights(_) => {
-                        unimplemented!("Should not be Morph target weights")
+                    gltf::animation::util::ReadOutputs::MorphTargetWeights(weights) => {
+                        if nodes_tree.nodes[node_id].weights.is_empty() {
+                            warn!(
+                                "Skipping morph target weights of node {} without morph targets",
+                                node_id
+                            );
+                            continue;
+                        }
+                        let weights: Vec<f32> = weights.into_f32().collect();
+                        if channels[node_id].is_none() {
+                            channels[node_id] = Some(NodeChannels::default());
+                        }
+                        channels[node_id].as_mut().unwrap().weights =
+                            Some(Channel::new(interpolation, times, ChannelType::Weights(weights)));
                     }
                 }
             }
 
-            animations.push(Animation::new(name, channels));
+            let mut animation_data = Animation::new(name, channels);
+            if let Some(extras) = animation.extras() {
+                let extras: AnimationExtras = serde_json::from_str(extras.get())
+                    .with_context(|| format!("Should have valid extras in animation {}", animation_data.name))?;
+                animation_data.add_events(extras.events);
+            }
+            animations.push(animation_data);
         }
         Ok(animations)
     }
 
     pub fn load_on_gpu(
         &mut self, device: &Device, queue: &Queue, texture_bind_group_layout: &BindGroupLayout,
-        joints_bind_group_layout: &BindGroupLayout,
+        joints_bind_group_layout: &BindGroupLayout, skinning_bind_group_layout: &BindGroupLayout,
     ) {
-        let joints = self.nodes_tree.get_joints_double_quat();
-        let joints: Vec<[[f32; 4]; 4]> = vec![Mat4::IDENTITY.to_cols_array_2d(); joints.len()];
+        // Room for the matrices of the linear skinning or the dual quaternions and scales, the larger of the two
+        let joint_size = size_of::<[[f32; 4]; 4]>().max(size_of::<[[f32; 4]; 5]>());
+        let joints: Vec<u8> = vec![0; self.nodes_tree.joints_len() * joint_size];
 
+        let compact_vertices: Vec<CompactVertex>;
+        let contents: &[u8] = match self.influence_layout() {
+            InfluenceLayout::Four => {
+                compact_vertices = self.vertices.iter().map(CompactVertex::from).collect();
+                bytemuck::cast_slice(&compact_vertices)
+            }
+            InfluenceLayout::Eight => bytemuck::cast_slice(&self.vertices),
+        };
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(&self.vertices),
-            usage: wgpu::BufferUsages::VERTEX,
+            contents,
+            // Also read by the compute skinning pass
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
         });
 
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(self.indices.as_slice()),
-            usage: wgpu::BufferUsages::INDEX,
-        });
+        if !self.indices.u16.is_empty() {
+            self.indices_u16_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Index Buffer u16"),
+                contents: bytemuck::cast_slice(self.indices.u16.as_slice()),
+                usage: wgpu::BufferUsages::INDEX,
+            }));
+        }
+        if !self.indices.u32.is_empty() {
+            self.indices_u32_buffer = Some(device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Index Buffer u32"),
+                contents: bytemuck::cast_slice(self.indices.u32.as_slice()),
+                usage: wgpu::BufferUsages::INDEX,
+            }));
+        }
 
-        let mut texture_buffer = Texture::from_bytes(
-            device,
-            queue,
-            &self.texture.data_rgba,
-            self.texture.width,
-            self.texture.height,
-        );
-        texture_buffer.create_texture_group(device, texture_bind_group_layout);
+        for material in self.materials.iter_mut() {
+            let mut texture_buffer = Texture::from_bytes(
+                device,
+                queue,
+                &material.texture.data_rgba,
+                material.texture.width,
+                material.texture.height,
+            );
+            texture_buffer.create_texture_group(device, texture_bind_group_layout);
+            material.texture_buffer = Some(texture_buffer);
+        }
 
         let joints_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Joints Buffer"),
@@ -291,43 +644,273 @@ impl Modelv2 {
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
 
+        // Storage buffers can not be empty
+        let morph_deltas = if self.morph_deltas.is_empty() {
+            vec![MorphDelta::default()]
+        } else {
+            self.morph_deltas.clone()
+        };
+        let morph_deltas_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Morph Deltas Buffer"),
+            contents: bytemuck::cast_slice(morph_deltas.as_slice()),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let mut morph_weights = self.get_morph_weights();
+        if morph_weights.is_empty() {
+            morph_weights.push(0.0);
+        }
+        let morph_weights_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Morph Weights Buffer"),
+            contents: bytemuck::cast_slice(morph_weights.as_slice()),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
         let joints_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: joints_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: joints_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: joints_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: morph_deltas_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: morph_weights_buffer.as_entire_binding(),
+                },
+            ],
             label: Some("joints_bind_group"),
         });
 
+        let skinned_vertices_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Skinned Vertex Buffer"),
+            size: (self.vertices.len() * size_of::<SkinnedVertex>()) as wgpu::BufferAddress,
+            // Written by the compute pass or from the CPU skinning, read back to compare them
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let skinning_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: skinning_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: vertex_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: joints_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: morph_deltas_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: morph_weights_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: skinned_vertices_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("skinning_bind_group"),
+        });
+
         self.vertices_buffer = Some(vertex_buffer);
-        self.indices_buffer = Some(index_buffer);
-        self.texture_buffer = Some(texture_buffer);
         self.joints_buffer = Some(joints_buffer);
+        self.morph_deltas_buffer = Some(morph_deltas_buffer);
+        self.morph_weights_buffer = Some(morph_weights_buffer);
         self.joints_bind_group = Some(joints_bind_group);
+        self.skinned_vertices_buffer = Some(skinned_vertices_buffer);
+        self.skinning_bind_group = Some(skinning_bind_group);
     }
 
-    pub fn render_animation(
-        &mut self, time: f32, animation_index: Option<usize>, queue: &Queue, double_quat_joints_render: bool,
-    ) {
-        let animation = match animation_index {
-            Some(index) => &self.animations[index],
-            None => &self.animations[0],
-        };
+    /// Vertices of the current pose skinned on the CPU, in model space
+    pub fn skin_vertices(&self, double_quat_joints_render: bool) -> Vec<SkinnedVertex> {
+        let morph_weights = self.get_morph_weights();
+        if double_quat_joints_render {
+            let joints = self.nodes_tree.get_joints_double_quat();
+            cpu_skinning::skin_dual_quat(&self.vertices, &self.morph_deltas, &morph_weights, &joints)
+        } else {
+            let joints = self.nodes_tree.get_joints();
+            cpu_skinning::skin_linear(&self.vertices, &self.morph_deltas, &morph_weights, &joints)
+        }
+    }
 
-        for (node_index, node) in self.nodes_tree.nodes.iter_mut().enumerate() {
-            let channels = &animation.channels[node_index];
-            if let Some(channels) = channels {
-                channels.eval(time, node);
+    /// Skin the vertices on the CPU and upload them for `draw_skinned`
+    pub fn upload_skinned_vertices(&self, queue: &Queue, double_quat_joints_render: bool) {
+        queue.write_buffer(
+            self.skinned_vertices_buffer.as_ref().unwrap(),
+            0,
+            bytemuck::cast_slice(self.skin_vertices(double_quat_joints_render).as_slice()),
+        );
+    }
+
+    pub fn influence_report(&self) -> &InfluenceReport {
+        &self.influence_report
+    }
+
+    /// Vertex layout and shader variant fitting the joint influences of the model
+    pub fn influence_layout(&self) -> InfluenceLayout {
+        InfluenceLayout::for_influences(self.influence_report.used_influences)
+    }
+
+    pub fn skinning_bind_group(&self) -> Option<&BindGroup> {
+        self.skinning_bind_group.as_ref()
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.vertices.len() as u32
+    }
+
+    pub fn bind_pose(&self) -> Pose {
+        self.nodes_tree.bind_pose().clone()
+    }
+
+    /// Sample an animation at `time`, the nodes it does not animate keep their bind pose. The root motion is removed
+    /// unless it is off for the animation.
+    pub fn sample_pose(&self, animation_index: usize, time: f32) -> Pose {
+        let mut pose = self.sample_raw_pose(animation_index, time);
+        if let (Some(root), Some(root_motion)) = (self.root_joint, self.root_motions.get(animation_index)) {
+            if root_motion.mode != RootMotionMode::Off {
+                root_motion.remove(&mut pose, self.nodes_tree.parents(), root);
             }
         }
+        pose
+    }
+
+    fn sample_raw_pose(&self, animation_index: usize, time: f32) -> Pose {
+        let mut pose = self.nodes_tree.bind_pose().clone();
+        if let Some(animation) = self.animations.get(animation_index) {
+            animation.sample(time, &mut pose);
+        }
+        pose
+    }
+
+    pub fn set_root_motion_mode(&mut self, animation_index: usize, mode: RootMotionMode) {
+        if let Some(root_motion) = self.root_motions.get_mut(animation_index) {
+            root_motion.mode = mode;
+        }
+    }
+
+    /// Motion of the model while the animation plays from `from` to `to`, as a translation in model space and a yaw.
+    /// None unless the root motion of the animation is applied.
+    pub fn root_motion_delta(&self, animation_index: usize, from: f32, to: f32) -> Option<(Vec3, f32)> {
+        let root = self.root_joint?;
+        let root_motion = self.root_motions.get(animation_index)?;
+        if root_motion.mode != RootMotionMode::Apply {
+            return None;
+        }
+        let parents = self.nodes_tree.parents();
+        let from = RootTransform::from_pose(&self.sample_raw_pose(animation_index, from), parents, root);
+        let to = RootTransform::from_pose(&self.sample_raw_pose(animation_index, to), parents, root);
+        Some(root_motion.delta(&from, &to))
+    }
+
+    pub fn root_joint(&self) -> Option<usize> {
+        self.root_joint
+    }
+
+    pub fn apply_pose(&mut self, pose: &Pose) {
+        pose.apply(&mut self.nodes_tree);
+    }
+
+    /// World position of a node of the current pose
+    pub fn node_position(&self, node: usize, model_matrix: Mat4) -> Vec3 {
+        model_matrix.transform_point3(self.nodes_tree.get_global_transform(node).w_axis.truncate())
+    }
+
+    /// Nodes from `root` down to `effector`, None when `root` is not an ancestor of `effector`
+    pub fn ik_chain(&self, root: usize, effector: usize) -> Option<Vec<usize>> {
+        self.nodes_tree.chain(root, effector)
+    }
+
+    /// Rotate the nodes of a chain so its effector reaches a world space target, with cyclic coordinate descent.
+    /// Return the distance left in model space.
+    pub fn solve_ccd(&mut self, chain: &[usize], target: Vec3, model_matrix: Mat4, settings: &IkSettings) -> f32 {
+        let target = model_matrix.inverse().transform_point3(target);
+        ik::solve_ccd(&mut self.nodes_tree, chain, target, settings)
+    }
+
+    /// Rotate the last three nodes of a chain so its effector reaches a world space target in closed form, bending
+    /// toward a world space pole. Return the distance left in model space.
+    pub fn solve_two_bone(
+        &mut self, chain: &[usize], target: Vec3, pole: Option<Vec3>, softness: f32, model_matrix: Mat4,
+    ) -> f32 {
+        let inverse = model_matrix.inverse();
+        let pole = pole.map(|pole| inverse.transform_point3(pole));
+        ik::solve_two_bone(
+            &mut self.nodes_tree,
+            chain,
+            inverse.transform_point3(target),
+            pole,
+            softness,
+        )
+    }
+
+    /// Move the nodes of chains toward world space targets with forward and backward reaching, the chains sharing
+    /// nodes are solved together. The distances are in model space, the bones of the solution in world space.
+    pub fn solve_fabrik(
+        &mut self, chains: &[(Vec<usize>, Vec3)], model_matrix: Mat4, settings: &IkSettings,
+    ) -> ik::FabrikSolution {
+        let inverse = model_matrix.inverse();
+        let chains: Vec<(Vec<usize>, Vec3)> = chains
+            .iter()
+            .map(|(chain, target)| (chain.clone(), inverse.transform_point3(*target)))
+            .collect();
+        let mut solution = ik::solve_fabrik(&mut self.nodes_tree, &chains, settings);
+        for (from, to) in solution.bones.iter_mut() {
+            *from = model_matrix.transform_point3(*from);
+            *to = model_matrix.transform_point3(*to);
+        }
+        solution
+    }
+
+    /// Plant the feet of `legs`, each an upper leg, a lower leg and a foot, on the world space `ground`, moving the
+    /// root joint up or down. Return the contacts found, in world space.
+    pub fn solve_foot_ik(&mut self, legs: &[[usize; 3]], ground: &Ground, model_matrix: Mat4) -> Vec<GroundHit> {
+        match self.root_joint {
+            Some(pelvis) => foot_ik::solve_foot_ik(&mut self.nodes_tree, legs, pelvis, ground, model_matrix),
+            None => Vec::new(),
+        }
+    }
+
+    /// Direction in the local space of a node of the current pose that points to a model space `direction`
+    pub fn local_axis(&self, node: usize, direction: Vec3) -> Vec3 {
+        let (_, rotation, _) = self.nodes_tree.get_global_transform(node).to_scale_rotation_translation();
+        rotation.inverse() * direction
+    }
+
+    /// Turn the joints of a look-at constraint toward its smoothed target, in world space. Return the angle left.
+    pub fn solve_look_at(&mut self, look_at: &LookAt, model_matrix: Mat4) -> f32 {
+        let joints: Vec<(usize, f32, f32)> = look_at
+            .joints
+            .iter()
+            .filter_map(|joint| Some((joint.node?, joint.weight, joint.limit)))
+            .collect();
+        let target = model_matrix.inverse().transform_point3(look_at.smoothed_target);
+        look_at::solve_look_at(&mut self.nodes_tree, &joints, look_at.forward, target)
+    }
+
+    /// Upload the joints and morph weights of the current nodes
+    pub fn render_animation(&mut self, queue: &Queue, double_quat_joints_render: bool) {
+        if !self.morph_nodes.is_empty() {
+            queue.write_buffer(
+                self.morph_weights_buffer.as_ref().unwrap(),
+                0,
+                bytemuck::cast_slice(self.get_morph_weights().as_slice()),
+            );
+        }
 
         if double_quat_joints_render {
             let joints = self.nodes_tree.get_joints_double_quat();
-            let joints: Vec<[[f32; 4]; 2]> = joints
-                .iter()
-                .map(|j| [[j[0].x, j[0].y, j[0].z, j[0].w], [j[1].x, j[1].y, j[1].z, j[1].w]])
-                .collect();
+            let joints: Vec<[[f32; 4]; 5]> = joints.iter().map(|j| j.to_gpu()).collect();
             queue.write_buffer(
                 self.joints_buffer.as_ref().unwrap(),
                 0,
@@ -344,22 +927,63 @@ impl Modelv2 {
         }
     }
 
+    fn get_morph_weights(&self) -> Vec<f32> {
+        self.morph_nodes
+            .iter()
+            .flat_map(|node| self.nodes_tree.nodes[*node].weights.iter().copied())
+            .collect()
+    }
+
     pub fn draw(&self, render_pass: &mut wgpu::RenderPass) {
         render_pass.set_vertex_buffer(0, self.vertices_buffer.as_ref().unwrap().slice(..));
-        render_pass.set_index_buffer(
-            self.indices_buffer.as_ref().unwrap().slice(..),
-            wgpu::IndexFormat::Uint16,
-        );
-        render_pass.set_bind_group(0, self.texture_buffer.as_ref().unwrap().get_bind_group(), &[]);
         render_pass.set_bind_group(3, self.joints_bind_group.as_ref().unwrap(), &[]);
-        render_pass.draw_indexed(0..self.indices.len() as u32, 0, 0..1);
+        self.draw_submeshes(render_pass);
+    }
+
+    /// Draw the vertices written by the compute skinning pass, as static geometry
+    pub fn draw_skinned(&self, render_pass: &mut wgpu::RenderPass) {
+        render_pass.set_vertex_buffer(0, self.skinned_vertices_buffer.as_ref().unwrap().slice(..));
+        self.draw_submeshes(render_pass);
+    }
+
+    fn draw_submeshes(&self, render_pass: &mut wgpu::RenderPass) {
+        for submesh in self.submeshes.iter() {
+            let indices_buffer = match submesh.index_format {
+                IndexFormat::Uint16 => self.indices_u16_buffer.as_ref(),
+                IndexFormat::Uint32 => self.indices_u32_buffer.as_ref(),
+            };
+            render_pass.set_index_buffer(indices_buffer.unwrap().slice(..), submesh.index_format);
+            let material = &self.materials[submesh.material];
+            render_pass.set_bind_group(0, material.texture_buffer.as_ref().unwrap().get_bind_group(), &[]);
+            render_pass.draw_indexed(
+                submesh.index_start..submesh.index_start + submesh.index_count,
+                submesh.base_vertex,
+                0..1,
+            );
+        }
+    }
+
+    /// Name and parent of every node
+    pub fn get_node_hierarchy(&self) -> (Vec<String>, Vec<Option<usize>>) {
+        let names = self.nodes_tree.nodes.iter().map(|node| node.name.clone()).collect();
+        (names, self.nodes_tree.parents().to_vec())
+    }
+
+    /// Mask over the node named `root_name` and all its descendants
+    pub fn create_mask(&self, root_name: &str) -> Option<BoneMask> {
+        let root = self.nodes_tree.find_node(root_name)?;
+        Some(BoneMask::from_subtree(
+            root_name.to_string(),
+            self.nodes_tree.parents(),
+            root,
+        ))
     }
 
     pub fn get_animation_names(&self) -> Vec<String> {
         self.animations.iter().map(|a| a.name.clone()).collect()
     }
-    
-    pub fn animations (&self) -> &Vec<Animation> {
+
+    pub fn animations(&self) -> &Vec<Animation> {
         &self.animations
     }
 }
@@ -367,10 +991,394 @@ impl Modelv2 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::skinning::SkinningPass;
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
 
     #[test]
     fn test_load() {
-        let model = Modelv2::load_woman();
+        let model = Modelv2::load(&Path::new("rsc").join("Woman.gltf"));
         model.unwrap();
     }
+
+    #[test]
+    fn test_load_static_meshes() {
+        let duck = Modelv2::load(&Path::new("rsc").join("duck").join("glTF").join("Duck.gltf")).unwrap();
+        assert!(duck.animations().is_empty());
+        assert_eq!(duck.nodes_tree.joints_len(), 1);
+
+        let lantern = Modelv2::load(&Path::new("rsc").join("lantern").join("Lantern.gltf")).unwrap();
+        assert_eq!(lantern.nodes_tree.joints_len(), 3);
+        // The three meshes share the same material
+        assert_eq!(lantern.submeshes.len(), 3);
+        assert_eq!(lantern.materials.len(), 1);
+        assert!(lantern.indices.u32.is_empty());
+        let last = lantern.submeshes.last().unwrap();
+        assert_eq!(
+            (last.index_start + last.index_count) as usize,
+            lantern.indices.u16.len()
+        );
+    }
+
+    #[test]
+    fn test_max_influences() {
+        let path = Path::new("rsc").join("Woman.gltf");
+        let model = Modelv2::load(&path).unwrap();
+        let report = model.influence_report();
+        assert_eq!(report.max_influences, MAX_JOINT_INFLUENCES);
+        assert_eq!(report.skinned_vertices, model.vertices.len());
+        assert_eq!(report.truncated_vertices, 0);
+        // No vertex has more than 4 joints, the compact layout holds them
+        assert!(report.used_influences <= 4);
+        assert_eq!(model.influence_layout(), InfluenceLayout::Four);
+
+        let limited = Modelv2::load_with_max_influences(&path, 1).unwrap();
+        let report = limited.influence_report();
+        assert!(report.truncated_vertices > 0);
+        assert!(report.max_dropped_weight >= report.mean_dropped_weight() && report.max_dropped_weight < 1.0);
+        for vertex in &limited.vertices {
+            assert_eq!(vertex.joints_weights[0], 1.0);
+        }
+    }
+
+    #[test]
+    fn test_eight_influences() {
+        let path = Path::new("rsc").join("influences").join("EightInfluences.gltf");
+        let model = Modelv2::load(&path).unwrap();
+        let report = model.influence_report();
+        assert_eq!(report.skinned_vertices, 3);
+        assert_eq!(report.truncated_vertices, 0);
+        assert_eq!(report.used_influences, 8);
+        assert_eq!(model.influence_layout(), InfluenceLayout::Eight);
+        // The weights of the first vertex sum to 2, the strongest are first in the order they were read
+        let vertex = &model.vertices[0];
+        assert_eq!(vertex.affected_joints[..6], [0, 1, 4, 5, 2, 3]);
+        for (weight, expected) in vertex.joints_weights.iter().zip([0.2, 0.2, 0.2, 0.2, 0.1, 0.1, 0.0, 0.0]) {
+            assert!((weight - expected).abs() < 1e-6, "{:?}", vertex.joints_weights);
+        }
+        assert_eq!(model.vertices[1].joints_weights, [0.5, 0.5, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(model.vertices[2].affected_joints, [0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(model.vertices[2].joints_weights, [0.125; 8]);
+
+        // Kept to 4 influences, the first and last vertices lose weight
+        let limited = Modelv2::load_with_max_influences(&path, 4).unwrap();
+        let report = limited.influence_report();
+        assert_eq!(report.truncated_vertices, 2);
+        assert_eq!(report.used_influences, 4);
+        assert!((report.max_dropped_weight - 0.5).abs() < 1e-6);
+        assert!((report.mean_dropped_weight() - 0.35).abs() < 1e-6);
+        assert_eq!(limited.influence_layout(), InfluenceLayout::Four);
+        assert_eq!(limited.vertices[0].joints_weights[..4], [0.25; 4]);
+    }
+
+    #[test]
+    fn test_load_morph_targets() {
+        let model = Modelv2::load(&Path::new("rsc").join("morph").join("MorphTriangle.gltf")).unwrap();
+        let mesh = model.nodes_tree.find_node("Mesh").unwrap();
+        let over = model.nodes_tree.find_node("Override").unwrap();
+        // The nodes are visited from the last, the weights of a node follow the ones of the previous node
+        assert_eq!(model.morph_nodes, vec![mesh, over]);
+        assert_eq!(model.get_morph_weights(), vec![0.25, 0.5, 1.0, 0.0]);
+
+        assert_eq!(model.vertices.len(), 6);
+        for (index, vertex) in model.vertices.iter().enumerate() {
+            let weights_start = if index < 3 { 0 } else { 2 };
+            assert_eq!(vertex.morph_targets, [2 * index as u32, weights_start, 2]);
+        }
+
+        // Both targets of a vertex are next to each other, the second has no normals
+        let expected = [
+            ([0.0, 0.0, 0.5], [0.0, 0.5, 0.0], [0.25, 0.0, 0.0]),
+            ([0.0, 0.0, 0.0], [0.0, 0.0, 0.0], [0.5, 0.0, 0.0]),
+            ([0.0, 0.0, -0.5], [0.0, -0.5, 0.0], [0.75, 0.0, 0.0]),
+        ];
+        assert_eq!(model.morph_deltas.len(), 12);
+        for (index, deltas) in model.morph_deltas.chunks(2).enumerate() {
+            let (position, normal, second_position) = expected[index % 3];
+            assert_eq!(deltas[0].position[..3], position);
+            assert_eq!(deltas[0].normal[..3], normal);
+            assert_eq!(deltas[1].position[..3], second_position);
+            assert_eq!(deltas[1].normal, [0.0; 4]);
+        }
+    }
+
+    #[test]
+    fn test_indices_format() {
+        let mut indices = Indices::default();
+
+        let small: Vec<u32> = (0..=u16::MAX as u32).collect();
+        assert_eq!(indices.push(&small), (IndexFormat::Uint16, 0));
+
+        let large: Vec<u32> = (0..70_000).collect();
+        assert_eq!(indices.push(&large), (IndexFormat::Uint32, 0));
+        assert_eq!(indices.push(&[0, 1, 2]), (IndexFormat::Uint16, small.len() as u32));
+        assert_eq!(indices.push(&[0, 1, 70_000]), (IndexFormat::Uint32, large.len() as u32));
+        assert_eq!(indices.u32[large.len() + 2], 70_000);
+    }
+
+    #[test]
+    fn test_sample_pose_keeps_bind_pose() {
+        let model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let bind_pose = model.nodes_tree.bind_pose();
+        for (index, animation) in model.animations().iter().enumerate() {
+            let pose = model.sample_pose(index, animation.duration() / 2.0);
+            for (node_index, channels) in animation.channels.iter().enumerate() {
+                if channels.is_none() {
+                    assert_eq!(pose.nodes[node_index], bind_pose.nodes[node_index]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_cubic_spline_rotation() {
+        // Keyframes at 0, 0.5 and 2 seconds. `Flat` has zero tangents, half way between two keyframes it is half way
+        // through the turn. `Tangents` stores the values and derivatives of a cubic polynomial, which a cubic spline
+        // follows exactly when the tangents are scaled by the length of the keyframes.
+        let path = Path::new("rsc").join("cubic_spline").join("CubicSplineRotation.gltf");
+        let model = Modelv2::load(&path).unwrap();
+        let flat = model.nodes_tree.find_node("Flat").unwrap();
+        let tangents = model.nodes_tree.find_node("Tangents").unwrap();
+        assert_eq!(model.animations()[0].duration(), 2.0);
+        let polynomial = |t: f32| {
+            let (t2, t3) = (t * t, t * t * t);
+            Quat::from_xyzw(
+                0.1 * t,
+                0.4 * t - 0.05 * t2 + 0.02 * t3,
+                -0.05 * t + 0.03 * t3,
+                1.0 + 0.033_691_265 * t - 0.156_263_43 * t2 + 0.02 * t3,
+            )
+            .normalize()
+        };
+        let samples = [
+            (0.25, Quat::from_rotation_x(FRAC_PI_4)),
+            (0.5, Quat::from_rotation_x(FRAC_PI_2)),
+            (1.25, Quat::from_rotation_x(3.0 * FRAC_PI_4)),
+        ];
+        for (time, flat_rotation) in samples {
+            let rotate = model.sample_raw_pose(0, time).nodes[flat].rotate;
+            assert!(rotate.abs_diff_eq(flat_rotation, 1e-5), "{} {:?}", time, rotate);
+        }
+        for time in [0.1, 0.25, 0.5, 0.8, 1.25, 1.75, 2.0] {
+            let rotate = model.sample_raw_pose(0, time).nodes[tangents].rotate;
+            assert!(rotate.abs_diff_eq(polynomial(time), 1e-5), "{} {:?}", time, rotate);
+        }
+    }
+
+    #[test]
+    fn test_load_events_sidecar() {
+        let model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let walking = model.animations().iter().find(|a| a.name == "Walking").unwrap();
+        let names: Vec<&str> = walking.events.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["footstep_left", "footstep_right"]);
+        // A loop of the whole clip crosses every event once
+        assert_eq!(walking.crossed_events(0.0, walking.duration()).count(), 2);
+    }
+
+    #[test]
+    fn test_root_motion() {
+        let mut model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let sitting = model.animations().iter().position(|a| a.name == "Sitting").unwrap();
+        let duration = model.animations()[sitting].duration();
+        let root = model.root_joint.unwrap();
+        assert_eq!(model.nodes_tree.nodes[root].name, "Hips");
+        let parents = model.nodes_tree.parents().to_vec();
+        let start = model.root_motions[sitting].start;
+
+        // The clip moves back while sitting down
+        let end = RootTransform::from_pose(&model.sample_pose(sitting, duration), &parents, root);
+        assert!(end.position.distance(start.position) > 0.5);
+        assert_eq!(model.root_motion_delta(sitting, 0.0, duration), None);
+
+        model.set_root_motion_mode(sitting, RootMotionMode::InPlace);
+        let in_place = RootTransform::from_pose(&model.sample_pose(sitting, duration), &parents, root);
+        assert!(in_place.position.abs_diff_eq(start.position, 1e-4));
+        assert!((in_place.yaw - start.yaw).abs() < 1e-4);
+        assert_eq!(model.root_motion_delta(sitting, 0.0, duration), None);
+
+        // Applied in two steps, the second one turned by the yaw of the first, it moves as far as in one
+        model.set_root_motion_mode(sitting, RootMotionMode::Apply);
+        let (translation, yaw) = model.root_motion_delta(sitting, 0.0, duration).unwrap();
+        let (first, first_yaw) = model.root_motion_delta(sitting, 0.0, duration / 2.0).unwrap();
+        let (second, second_yaw) = model.root_motion_delta(sitting, duration / 2.0, duration).unwrap();
+        assert!(translation.length() > 0.5);
+        assert!((first + Quat::from_rotation_y(first_yaw) * second).abs_diff_eq(translation, 1e-3));
+        assert!((first_yaw + second_yaw - yaw).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_fabrik_two_hands() {
+        let mut model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let node = |name: &str| model.nodes_tree.find_node(name).unwrap();
+        let (spine, left_hand, right_hand) = (node("Spine"), node("LeftHand"), node("RightHand"));
+        let left = model.ik_chain(spine, left_hand).unwrap();
+        let right = model.ik_chain(spine, right_hand).unwrap();
+
+        // Both hands moved forward, holding a prop
+        let offset = Vec3::new(0.0, 0.1, 0.2);
+        let targets = [
+            model.node_position(left_hand, Mat4::IDENTITY) + offset,
+            model.node_position(right_hand, Mat4::IDENTITY) + offset,
+        ];
+        let settings = IkSettings {
+            iterations: 30,
+            tolerance: 0.001,
+        };
+        let solution = model.solve_fabrik(
+            &[(left, targets[0]), (right, targets[1])],
+            Mat4::IDENTITY,
+            &settings,
+        );
+        assert!(solution.distances.iter().all(|distance| *distance < 0.01), "{:?}", solution.distances);
+        // The spine nodes are shared, each one is a single bone
+        assert_eq!(solution.bones.len(), 2 + 4 + 4);
+        assert!(model.node_position(left_hand, Mat4::IDENTITY).distance(targets[0]) < 0.01);
+    }
+
+    #[test]
+    fn test_two_bone_leg() {
+        let mut model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let up_leg = model.nodes_tree.find_node("LeftUpLeg").unwrap();
+        let foot = model.nodes_tree.find_node("LeftFoot").unwrap();
+        let chain = model.ik_chain(up_leg, foot).unwrap();
+        let knee = model.node_position(chain[1], Mat4::IDENTITY);
+
+        // Foot raised, the knee comes forward toward the pole
+        let target = model.node_position(foot, Mat4::IDENTITY) + Vec3::new(0.0, 0.3, 0.1);
+        let pole = knee + Vec3::Z;
+        let distance = model.solve_two_bone(&chain, target, Some(pole), 0.0, Mat4::IDENTITY);
+        assert!(distance < 1e-3, "{}", distance);
+        assert!(model.node_position(chain[1], Mat4::IDENTITY).z > knee.z);
+    }
+
+    #[test]
+    fn test_foot_ik() {
+        let mut model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let legs = ["Left", "Right"].map(|side| {
+            ["UpLeg", "Leg", "Foot"].map(|name| model.nodes_tree.find_node(&format!("{}{}", side, name)).unwrap())
+        });
+        let feet = legs.map(|leg| model.node_position(leg[2], Mat4::IDENTITY));
+
+        // Ground raised by 0.1, both feet go up by as much
+        let ground = Ground::plane(Vec3::new(0.0, 0.1, 0.0));
+        let contacts = model.solve_foot_ik(&legs, &ground, Mat4::IDENTITY);
+        assert_eq!(contacts.len(), 2);
+        for (leg, foot) in legs.iter().zip(feet) {
+            let position = model.node_position(leg[2], Mat4::IDENTITY);
+            assert!(position.abs_diff_eq(foot + Vec3::new(0.0, 0.1, 0.0), 1e-3), "{} {}", position, foot);
+        }
+    }
+
+    #[test]
+    fn test_look_at_head() {
+        let mut model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let joints = ["Spine2", "Neck", "Head"].map(|name| model.nodes_tree.find_node(name).unwrap());
+        let head = joints[2];
+        let forward = model.local_axis(head, Vec3::Z);
+
+        // Target to the side of the head, within the limits
+        let position = model.node_position(head, Mat4::IDENTITY);
+        let mut look_at = LookAt::new(
+            joints.iter().map(|joint| LookAtJoint::new(Some(*joint), 0.3, 1.0)).collect(),
+            forward,
+            position + Vec3::new(1.0, 0.0, 1.0),
+        );
+        look_at.joints[2].weight = 1.0;
+        let angle = model.solve_look_at(&look_at, Mat4::IDENTITY);
+        assert!(angle < 1e-3, "{}", angle);
+        let direction = model.local_axis(head, Vec3::Z);
+        assert!(!direction.abs_diff_eq(forward, 1e-2));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter, run with cargo test -- --ignored"]
+    fn test_skinning_pass_matches_cpu() {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default()))
+            .expect("Should find a GPU adapter to compare the skinning pass with");
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                required_limits: adapter.limits(),
+                ..Default::default()
+            },
+            None,
+        ))
+        .unwrap();
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: None,
+        });
+        let storage = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let joints_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[storage(0), storage(1), storage(2)],
+            label: None,
+        });
+        let mut model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let mut pose = model.sample_pose(0, 0.5);
+        // A leg flattened to zero, its normals cannot use the inverse transpose
+        let up_leg = model.nodes_tree.find_node("LeftUpLeg").unwrap();
+        pose.nodes[up_leg].scale = Vec3::new(0.0, 1.0, 1.0);
+        model.apply_pose(&pose);
+        // Woman fits the compact layout, both are uploaded to compare the shader variants
+        for (layout, double_quat) in [InfluenceLayout::Four, InfluenceLayout::Eight]
+            .into_iter()
+            .flat_map(|layout| [(layout, false), (layout, true)])
+        {
+            model.influence_report.used_influences = layout.influences();
+            let skinning_pass = SkinningPass::new(&device, layout);
+            model.load_on_gpu(&device, &queue, &texture_layout, &joints_layout, skinning_pass.bind_group_layout());
+            model.render_animation(&queue, double_quat);
+            let size = (model.vertices.len() * size_of::<SkinnedVertex>()) as wgpu::BufferAddress;
+            let readback = device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let mut encoder = device.create_command_encoder(&Default::default());
+            skinning_pass.dispatch(&mut encoder, &model, double_quat);
+            encoder.copy_buffer_to_buffer(model.skinned_vertices_buffer.as_ref().unwrap(), 0, &readback, 0, size);
+            queue.submit([encoder.finish()]);
+            readback.slice(..).map_async(wgpu::MapMode::Read, |result| result.unwrap());
+            device.poll(wgpu::Maintain::Wait);
+
+            let gpu = readback.slice(..).get_mapped_range();
+            let gpu: &[SkinnedVertex] = bytemuck::cast_slice(&gpu);
+            let cpu = model.skin_vertices(double_quat);
+            assert_eq!(gpu.len(), cpu.len());
+            for (gpu, cpu) in gpu.iter().zip(&cpu) {
+                assert!(Vec3::from(gpu.position).abs_diff_eq(Vec3::from(cpu.position), 1e-4));
+                assert!(Vec3::from(gpu.normal).abs_diff_eq(Vec3::from(cpu.normal), 1e-3));
+                assert_eq!(gpu.uv, cpu.uv);
+            }
+        }
+    }
 }
@@ -1,5 +1,6 @@
+use crate::model::pose::Pose;
 use crate::utils_glam::decompose;
-use glam::Mat4;
+use glam::{Mat3, Mat4, Quat, Vec3};
 use gltf::scene::Transform;
 
 #[derive(Debug, Clone, Default)]
@@ -9,15 +10,73 @@ pub struct Node {
     pub translate: glam::Vec3,
     pub rotate: glam::Quat,
     pub scale: glam::Vec3,
+    /// Morph target weights of the mesh of the node
+    pub weights: Vec<f32>,
+}
+
+/// Joint transform for the dual quaternion skinning, a rigid transform and the scale and shear applied before it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DualQuatJoint {
+    pub real: Quat,
+    pub dual: Quat,
+    /// Blended linearly, apart from the dual quaternions
+    pub scale: Mat3,
+}
+
+impl DualQuatJoint {
+    /// Split a joint matrix into its rotation and translation and the rest of its upper 3x3
+    pub fn from_mat4(matrix: Mat4) -> Self {
+        let (_, orientation, translation, _, _) = decompose(matrix);
+        let orientation = orientation.normalize();
+        Self {
+            real: orientation,
+            dual: Quat::from_xyzw(translation.x, translation.y, translation.z, 0.0) * orientation * 0.5,
+            scale: Mat3::from_quat(orientation.inverse()) * Mat3::from_mat4(matrix),
+        }
+    }
+
+    /// Matrix of the whole transform, the scale then the rigid transform
+    pub fn to_mat4(self) -> Mat4 {
+        let translation = self.dual * self.real.conjugate() * 2.0;
+        Mat4::from_rotation_translation(self.real, Vec3::new(translation.x, translation.y, translation.z))
+            * Mat4::from_mat3(self.scale)
+    }
+
+    /// Layout of the joints buffer of the dual quaternion shader, the columns of the scale are padded to 16 bytes
+    pub fn to_gpu(self) -> [[f32; 4]; 5] {
+        [
+            self.real.to_array(),
+            self.dual.to_array(),
+            self.scale.x_axis.extend(0.0).to_array(),
+            self.scale.y_axis.extend(0.0).to_array(),
+            self.scale.z_axis.extend(0.0).to_array(),
+        ]
+    }
 }
 
 pub struct NodeTree {
     pub nodes: Vec<Node>,
+    /// Parent of each node, the hierarchy does not change once loaded
+    parents: Vec<Option<usize>>,
     joints_index: Vec<usize>,
     inverse_bind_matrices: Vec<Mat4>,
+    /// Transforms of the nodes as loaded, used for the nodes an animation does not animate
+    bind_pose: Pose,
 }
 
 impl NodeTree {
+    fn new(nodes: Vec<Node>) -> Self {
+        let mut tree = NodeTree {
+            parents: nodes.iter().map(|node| node.parent).collect(),
+            nodes,
+            inverse_bind_matrices: Vec::new(),
+            joints_index: Vec::new(),
+            bind_pose: Pose::default(),
+        };
+        tree.bind_pose = Pose::from_tree(&tree);
+        tree
+    }
+
     pub fn get_joints(&self) -> Vec<Mat4> {
         let mut joints = vec![Mat4::IDENTITY; self.joints_index.len()];
 
@@ -32,25 +91,62 @@ impl NodeTree {
         joints
     }
 
-    pub fn get_joints_double_quat(&self) -> Vec<[glam::Quat; 2]> {
-        let mut joints = vec![[glam::Quat::IDENTITY; 2]; self.joints_index.len()];
-
-        let mat_joints = self.get_joints();
-        for mat_index in 0..mat_joints.len() {
-            let mat = mat_joints[mat_index];
-            let (_, orientation, translation, _, _) = decompose(mat);
-            joints[mat_index][0] = orientation;
-            joints[mat_index][1] =
-                glam::Quat::from_xyzw(translation.x, translation.y, translation.z, 0.0) * orientation * 0.5;
-            /*joints[mat_index][0] = glam::Quat::from_mat4(&mat);
-            joints[mat_index][1] = glam::Quat::from_xyzw(mat.w_axis.x, mat.w_axis.y, mat.w_axis.z, 0.0) * joints[mat_index][0] * 0.5;*/
-        }
-        joints
+    pub fn get_joints_double_quat(&self) -> Vec<DualQuatJoint> {
+        self.get_joints().into_iter().map(DualQuatJoint::from_mat4).collect()
     }
 
     pub fn len(&self) -> usize {
         self.nodes.len()
     }
+
+    pub fn parent(&self, node_index: usize) -> Option<usize> {
+        self.nodes[node_index].parent
+    }
+
+    /// Nodes from `root` down to `effector`, None when `root` is not an ancestor of `effector`
+    pub fn chain(&self, root: usize, effector: usize) -> Option<Vec<usize>> {
+        let mut chain = vec![effector];
+        let mut current = effector;
+        while current != root {
+            current = self.nodes[current].parent?;
+            chain.push(current);
+        }
+        chain.reverse();
+        Some(chain)
+    }
+
+    /// Parent of each node
+    pub fn parents(&self) -> &[Option<usize>] {
+        &self.parents
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name == name)
+    }
+
+    pub fn bind_pose(&self) -> &Pose {
+        &self.bind_pose
+    }
+
+    /// First joint whose parent is not a joint, the root of the skeleton
+    pub fn root_joint(&self) -> Option<usize> {
+        self.joints_index.iter().copied().find(|joint| {
+            self.nodes[*joint]
+                .parent
+                .is_none_or(|parent| !self.joints_index.contains(&parent))
+        })
+    }
+
+    pub fn joints_len(&self) -> usize {
+        self.joints_index.len()
+    }
+
+    /// Register a node as a joint and return its index in the joints buffer
+    pub fn add_joint(&mut self, node_index: usize, inverse_bind_matrix: Mat4) -> usize {
+        self.joints_index.push(node_index);
+        self.inverse_bind_matrices.push(inverse_bind_matrix);
+        self.joints_index.len() - 1
+    }
 }
 
 impl NodeTree {
@@ -128,9 +224,7 @@ fn find_a_false(visited: &Vec<bool>) -> Option<usize> {
     }
     None
 }
-pub fn create_nodes_tree_from_joints(
-    joints: Vec<usize>, nodes: Vec<gltf::Node>, inverse_bind_matrices: Vec<Mat4>,
-) -> NodeTree {
+pub fn create_nodes_tree(nodes: &[gltf::Node]) -> NodeTree {
     let mut node_tree = vec![Node::default(); nodes.len()];
 
     for (node_index, node) in nodes.iter().enumerate() {
@@ -156,6 +250,11 @@ pub fn create_nodes_tree_from_joints(
         node_tree[node_index].rotate = r;
         node_tree[node_index].scale = s;
 
+        let weights = node.weights().or_else(|| node.mesh().and_then(|mesh| mesh.weights()));
+        if let Some(weights) = weights {
+            node_tree[node_index].weights = weights.to_vec();
+        }
+
         if let Some(name) = node.name() {
             node_tree[node_index].name = name.to_string();
         }
@@ -166,10 +265,37 @@ pub fn create_nodes_tree_from_joints(
         }
     }
 
-    NodeTree {
-        nodes: node_tree,
-        inverse_bind_matrices,
-        joints_index: joints,
+    NodeTree::new(node_tree)
+}
+
+/// Node trees built by hand for the tests of the solvers
+#[cfg(test)]
+pub(crate) mod test_utils {
+    use super::{Node, NodeTree};
+
+    /// Straight chain of `len` nodes, each one `offset` from its parent
+    pub fn create_chain(len: usize, offset: glam::Vec3) -> NodeTree {
+        let nodes: Vec<(Option<usize>, glam::Vec3)> = (0..len)
+            .map(|i| (i.checked_sub(1), if i == 0 { glam::Vec3::ZERO } else { offset }))
+            .collect();
+        create_tree(&nodes)
+    }
+
+    /// Tree of nodes given by their parent and translation
+    pub fn create_tree(nodes: &[(Option<usize>, glam::Vec3)]) -> NodeTree {
+        let nodes = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, (parent, translate))| Node {
+                parent: *parent,
+                name: format!("node{}", i),
+                translate: *translate,
+                rotate: glam::Quat::IDENTITY,
+                scale: glam::Vec3::ONE,
+                weights: Vec::new(),
+            })
+            .collect();
+        NodeTree::new(nodes)
     }
 }
 
@@ -186,6 +312,7 @@ mod tests {
             translate: glam::Vec3::new(1.0, 0.0, 0.0),
             rotate: glam::Quat::IDENTITY,
             scale: glam::Vec3::new(1.0, 1.0, 1.0),
+            weights: Vec::new(),
         };
         let child = Node {
             parent: Some(0),
@@ -193,15 +320,10 @@ mod tests {
             translate: glam::Vec3::new(1.0, 0.0, 0.0),
             rotate: glam::Quat::IDENTITY,
             scale: glam::Vec3::new(1.0, 1.0, 1.0),
+            weights: Vec::new(),
         };
 
-        let tree = vec![parent, child];
-
-        let node_tree = super::NodeTree {
-            nodes: tree,
-            inverse_bind_matrices: Vec::new(),
-            joints_index: Vec::new(),
-        };
+        let node_tree = super::NodeTree::new(vec![parent, child]);
 
         let child_transform = node_tree.get_global_transform(1);
         assert_eq!(
@@ -209,4 +331,28 @@ mod tests {
             glam::Mat4::from_translation(glam::Vec3::new(2.0, 0.0, 0.0))
         );
     }
+
+    #[test]
+    fn test_double_quat_joints_with_scale() {
+        // Stretched parent turned under its child, the child joint gets a shear
+        let mut tree = super::test_utils::create_tree(&[
+            (None, glam::Vec3::new(0.0, 1.0, 0.0)),
+            (Some(0), glam::Vec3::new(1.0, 0.0, 0.0)),
+        ]);
+        tree.nodes[0].scale = glam::Vec3::new(2.0, 0.5, 1.0);
+        tree.nodes[1].rotate = glam::Quat::from_rotation_z(PI / 4.0);
+        tree.nodes[1].scale = glam::Vec3::new(1.0, 3.0, 1.0);
+        tree.add_joint(0, glam::Mat4::IDENTITY);
+        tree.add_joint(1, glam::Mat4::from_translation(glam::Vec3::new(-1.0, -1.0, 0.0)));
+
+        for (matrix, joint) in tree.get_joints().iter().zip(tree.get_joints_double_quat()) {
+            assert!(
+                joint.to_mat4().abs_diff_eq(*matrix, 1e-5),
+                "{} {}",
+                joint.to_mat4(),
+                matrix
+            );
+            assert!((joint.real.length() - 1.0).abs() < 1e-5);
+        }
+    }
 }
@@ -0,0 +1,263 @@
+use crate::model::mask::BoneMask;
+use crate::model::nodes_tree::NodeTree;
+use glam::{Mat4, Quat, Vec3};
+
+/// Local transform and morph target weights of a node
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodePose {
+    pub translate: Vec3,
+    pub rotate: Quat,
+    pub scale: Vec3,
+    pub weights: Vec<f32>,
+}
+
+impl Default for NodePose {
+    fn default() -> Self {
+        Self {
+            translate: Vec3::ZERO,
+            rotate: Quat::IDENTITY,
+            scale: Vec3::ONE,
+            weights: Vec::new(),
+        }
+    }
+}
+
+impl NodePose {
+    /// Blend toward `other`, a weight of 0.0 keeps this pose and 1.0 gives `other`
+    pub fn blend(&mut self, other: &NodePose, weight: f32) {
+        self.translate = self.translate.lerp(other.translate, weight);
+        self.rotate = self.rotate.slerp(other.rotate, weight);
+        self.scale = self.scale.lerp(other.scale, weight);
+        for (w, other_w) in self.weights.iter_mut().zip(other.weights.iter()) {
+            *w += (other_w - *w) * weight;
+        }
+    }
+
+    /// Difference from `reference` to this pose, the rotation and scale are relative to the reference ones. The axes
+    /// where the reference scale is zero, as for hidden joints, keep a relative scale of 1.
+    pub fn difference(&self, reference: &NodePose) -> NodePose {
+        let hidden = reference.scale.abs().cmplt(Vec3::splat(f32::EPSILON));
+        let scale = Vec3::select(hidden, Vec3::ONE, self.scale / reference.scale);
+        NodePose {
+            translate: self.translate - reference.translate,
+            rotate: (reference.rotate.inverse() * self.rotate).normalize(),
+            scale,
+            weights: self
+                .weights
+                .iter()
+                .zip(reference.weights.iter())
+                .map(|(w, reference_w)| w - reference_w)
+                .collect(),
+        }
+    }
+
+    /// Add a difference computed by `difference` on top of this pose, scaled by `weight`
+    pub fn add(&mut self, delta: &NodePose, weight: f32) {
+        self.translate += delta.translate * weight;
+        self.rotate = (self.rotate * Quat::IDENTITY.slerp(delta.rotate, weight)).normalize();
+        self.scale *= Vec3::ONE.lerp(delta.scale, weight);
+        for (w, delta_w) in self.weights.iter_mut().zip(delta.weights.iter()) {
+            *w += delta_w * weight;
+        }
+    }
+}
+
+/// Local transforms of every node of a NodeTree, in the same order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pose {
+    pub nodes: Vec<NodePose>,
+}
+
+impl Pose {
+    /// Take the current transforms of the nodes
+    pub fn from_tree(tree: &NodeTree) -> Self {
+        let nodes = tree
+            .nodes
+            .iter()
+            .map(|node| NodePose {
+                translate: node.translate,
+                rotate: node.rotate,
+                scale: node.scale,
+                weights: node.weights.clone(),
+            })
+            .collect();
+        Self { nodes }
+    }
+
+    /// Blend every node toward `other`, a weight of 0.0 keeps this pose and 1.0 gives `other`
+    pub fn blend(&mut self, other: &Pose, weight: f32) {
+        for (node, other) in self.nodes.iter_mut().zip(other.nodes.iter()) {
+            node.blend(other, weight);
+        }
+    }
+
+    /// Blend toward `other` with the weight of each node scaled by the mask
+    pub fn blend_masked(&mut self, other: &Pose, weight: f32, mask: &BoneMask) {
+        for (i, (node, other)) in self.nodes.iter_mut().zip(other.nodes.iter()).enumerate() {
+            node.blend(other, weight * mask.weight(i));
+        }
+    }
+
+    /// Difference of every node from `reference`
+    pub fn difference(&self, reference: &Pose) -> Pose {
+        let nodes = self
+            .nodes
+            .iter()
+            .zip(reference.nodes.iter())
+            .map(|(node, reference)| node.difference(reference))
+            .collect();
+        Pose { nodes }
+    }
+
+    /// Add a difference computed by `difference` on top of every node, scaled by `weight`
+    pub fn add(&mut self, delta: &Pose, weight: f32) {
+        for (node, delta) in self.nodes.iter_mut().zip(delta.nodes.iter()) {
+            node.add(delta, weight);
+        }
+    }
+
+    /// Add a difference with the weight of each node scaled by the mask
+    pub fn add_masked(&mut self, delta: &Pose, weight: f32, mask: &BoneMask) {
+        for (i, (node, delta)) in self.nodes.iter_mut().zip(delta.nodes.iter()).enumerate() {
+            node.add(delta, weight * mask.weight(i));
+        }
+    }
+
+    /// Transform of a node in model space, `parents` gives the parent of each node
+    pub fn global_transform(&self, parents: &[Option<usize>], node: usize) -> Mat4 {
+        let pose = &self.nodes[node];
+        let local = Mat4::from_scale_rotation_translation(pose.scale, pose.rotate, pose.translate);
+        match parents[node] {
+            Some(parent) => self.global_transform(parents, parent) * local,
+            None => local,
+        }
+    }
+
+    /// Write the transforms in the nodes of the tree
+    pub fn apply(&self, tree: &mut NodeTree) {
+        for (node, pose) in tree.nodes.iter_mut().zip(self.nodes.iter()) {
+            node.translate = pose.translate;
+            node.rotate = pose.rotate;
+            node.scale = pose.scale;
+            node.weights.clone_from(&pose.weights);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn test_difference_zero_scale() {
+        let hidden = NodePose {
+            scale: Vec3::new(0.0, 1.0, 0.0),
+            ..Default::default()
+        };
+        let current = NodePose {
+            scale: Vec3::new(2.0, 2.0, 0.0),
+            ..Default::default()
+        };
+        let delta = current.difference(&hidden);
+        assert_eq!(delta.scale, Vec3::new(1.0, 2.0, 1.0));
+
+        let mut pose = NodePose::default();
+        pose.add(&delta, 0.5);
+        assert!(pose.scale.is_finite());
+        assert_eq!(pose.scale, Vec3::new(1.0, 1.5, 1.0));
+    }
+
+    #[test]
+    fn test_blend() {
+        let mut a = NodePose {
+            weights: vec![0.0, 1.0],
+            ..Default::default()
+        };
+        let b = NodePose {
+            translate: Vec3::new(2.0, 0.0, 0.0),
+            rotate: Quat::from_rotation_y(PI / 2.0),
+            scale: Vec3::new(3.0, 3.0, 3.0),
+            weights: vec![1.0, 0.0],
+        };
+
+        a.blend(&b, 0.5);
+        assert_eq!(a.translate, Vec3::new(1.0, 0.0, 0.0));
+        assert!(a.rotate.abs_diff_eq(Quat::from_rotation_y(PI / 4.0), 1e-6));
+        assert_eq!(a.scale, Vec3::new(2.0, 2.0, 2.0));
+        assert_eq!(a.weights, vec![0.5, 0.5]);
+
+        // The rotation takes the shortest path even when the quaternions are in opposite hemispheres
+        let mut a = NodePose::default();
+        let b = NodePose {
+            rotate: -Quat::from_rotation_y(PI / 2.0),
+            ..Default::default()
+        };
+        a.blend(&b, 0.5);
+        assert!(a.rotate.abs_diff_eq(Quat::from_rotation_y(PI / 4.0), 1e-6));
+    }
+
+    #[test]
+    fn test_additive() {
+        let reference = NodePose {
+            translate: Vec3::new(1.0, 0.0, 0.0),
+            rotate: Quat::from_rotation_x(PI / 4.0),
+            scale: Vec3::new(2.0, 2.0, 2.0),
+            weights: vec![0.5],
+        };
+        let pose = NodePose {
+            translate: Vec3::new(1.0, 2.0, 0.0),
+            rotate: Quat::from_rotation_x(PI / 4.0) * Quat::from_rotation_y(PI / 2.0),
+            scale: Vec3::new(4.0, 2.0, 2.0),
+            weights: vec![1.0],
+        };
+        let delta = pose.difference(&reference);
+
+        // The full delta on the reference gives back the pose
+        let mut result = reference.clone();
+        result.add(&delta, 1.0);
+        assert!(result.translate.abs_diff_eq(pose.translate, 1e-6));
+        assert!(result.rotate.abs_diff_eq(pose.rotate, 1e-6));
+        assert!(result.scale.abs_diff_eq(pose.scale, 1e-6));
+        assert_eq!(result.weights, pose.weights);
+
+        // Half of it on another base
+        let mut result = NodePose {
+            weights: vec![0.0],
+            ..Default::default()
+        };
+        result.add(&delta, 0.5);
+        assert!(result.translate.abs_diff_eq(Vec3::new(0.0, 1.0, 0.0), 1e-6));
+        assert!(result.rotate.abs_diff_eq(Quat::from_rotation_y(PI / 4.0), 1e-6));
+        assert!(result.scale.abs_diff_eq(Vec3::new(1.5, 1.0, 1.0), 1e-6));
+        assert_eq!(result.weights, vec![0.25]);
+    }
+
+    #[test]
+    fn test_blend_masked() {
+        let mut a = Pose {
+            nodes: vec![NodePose::default(); 2],
+        };
+        let b = Pose {
+            nodes: vec![
+                NodePose {
+                    translate: Vec3::X,
+                    ..Default::default()
+                };
+                2
+            ],
+        };
+        let mask = BoneMask {
+            name: "mask".to_string(),
+            weights: vec![0.0, 0.5],
+        };
+
+        a.blend_masked(&b, 1.0, &mask);
+        assert_eq!(a.nodes[0].translate, Vec3::ZERO);
+        assert_eq!(a.nodes[1].translate, Vec3::new(0.5, 0.0, 0.0));
+
+        a.add_masked(&b, 1.0, &mask);
+        assert_eq!(a.nodes[0].translate, Vec3::ZERO);
+        assert_eq!(a.nodes[1].translate, Vec3::new(1.0, 0.0, 0.0));
+    }
+}
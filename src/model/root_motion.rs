@@ -0,0 +1,144 @@
+use crate::model::pose::Pose;
+use glam::{Mat4, Quat, Vec3};
+use std::f32::consts::{PI, TAU};
+
+/// What is done with the horizontal motion of the root joint of a clip
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RootMotionMode {
+    /// The root moves in the pose as animated
+    #[default]
+    Off,
+    /// The motion is removed from the pose, the clip plays in place
+    InPlace,
+    /// The motion is removed from the pose and moves the model instead
+    Apply,
+}
+
+impl RootMotionMode {
+    pub const ALL: [RootMotionMode; 3] = [RootMotionMode::Off, RootMotionMode::InPlace, RootMotionMode::Apply];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RootMotionMode::Off => "Off",
+            RootMotionMode::InPlace => "In place",
+            RootMotionMode::Apply => "Apply",
+        }
+    }
+}
+
+/// Horizontal position and yaw of the root joint, in model space
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RootTransform {
+    pub position: Vec3,
+    pub yaw: f32,
+}
+
+impl RootTransform {
+    pub fn from_pose(pose: &Pose, parents: &[Option<usize>], root: usize) -> Self {
+        let (_, rotation, translation) = pose.global_transform(parents, root).to_scale_rotation_translation();
+        Self {
+            position: Vec3::new(translation.x, 0.0, translation.z),
+            yaw: yaw(rotation),
+        }
+    }
+}
+
+/// Root motion of a clip
+#[derive(Clone, Debug, Default)]
+pub struct RootMotion {
+    pub mode: RootMotionMode,
+    /// Root transform at the start of the clip, the root is held there when the motion is removed
+    pub start: RootTransform,
+}
+
+impl RootMotion {
+    /// Bring the horizontal position and the yaw of the root back to the start of the clip
+    pub fn remove(&self, pose: &mut Pose, parents: &[Option<usize>], root: usize) {
+        let parent_transform = match parents[root] {
+            Some(parent) => pose.global_transform(parents, parent),
+            None => Mat4::IDENTITY,
+        };
+        let (scale, rotation, translation) = pose.global_transform(parents, root).to_scale_rotation_translation();
+
+        let rotation = Quat::from_rotation_y(self.start.yaw - yaw(rotation)) * rotation;
+        let translation = Vec3::new(self.start.position.x, translation.y, self.start.position.z);
+        let local = parent_transform.inverse() * Mat4::from_scale_rotation_translation(scale, rotation, translation);
+        let (_, rotation, translation) = local.to_scale_rotation_translation();
+        pose.nodes[root].translate = translation;
+        pose.nodes[root].rotate = rotation;
+    }
+
+    /// Motion of the model from `from` to `to`, as a translation in model space and a yaw. The translation is
+    /// relative to the heading of the root at `from`, turned to the start heading the pose is held at.
+    pub fn delta(&self, from: &RootTransform, to: &RootTransform) -> (Vec3, f32) {
+        let heading = Quat::from_rotation_y(self.start.yaw - from.yaw);
+        let translation = heading * (to.position - from.position);
+        (translation, wrap_angle(to.yaw - from.yaw))
+    }
+}
+
+/// Rotation around the vertical axis, the twist part of the rotation
+fn yaw(rotation: Quat) -> f32 {
+    2.0 * rotation.y.atan2(rotation.w)
+}
+
+fn wrap_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::pose::NodePose;
+
+    #[test]
+    fn test_remove_and_delta() {
+        let parents = [None, Some(0)];
+        let pose_at = |x: f32, yaw: f32| Pose {
+            nodes: vec![
+                NodePose {
+                    scale: Vec3::splat(0.5),
+                    ..Default::default()
+                },
+                NodePose {
+                    translate: Vec3::new(x, 4.0, 0.0),
+                    rotate: Quat::from_rotation_y(yaw) * Quat::from_rotation_x(0.3),
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let start = RootTransform::from_pose(&pose_at(0.0, 0.0), &parents, 1);
+        let root_motion = RootMotion {
+            mode: RootMotionMode::Apply,
+            start,
+        };
+        let mut pose = pose_at(2.0, PI / 2.0);
+        let moved = RootTransform::from_pose(&pose, &parents, 1);
+        assert!(moved.position.abs_diff_eq(Vec3::new(1.0, 0.0, 0.0), 1e-5));
+        assert!((moved.yaw - PI / 2.0).abs() < 1e-5);
+
+        // The height and the rotation off the vertical axis are kept
+        root_motion.remove(&mut pose, &parents, 1);
+        assert!(pose.nodes[1].translate.abs_diff_eq(Vec3::new(0.0, 4.0, 0.0), 1e-5));
+        assert!(pose.nodes[1].rotate.abs_diff_eq(Quat::from_rotation_x(0.3), 1e-5));
+
+        let (translation, yaw) = root_motion.delta(&start, &moved);
+        assert!(translation.abs_diff_eq(Vec3::new(1.0, 0.0, 0.0), 1e-5));
+        assert!((yaw - PI / 2.0).abs() < 1e-5);
+
+        // Moving forward after a turn is moving forward from the start heading
+        let further = RootTransform {
+            position: Vec3::new(1.0, 0.0, -1.0),
+            yaw: PI / 2.0,
+        };
+        let (translation, _) = root_motion.delta(&moved, &further);
+        assert!(translation.abs_diff_eq(Vec3::new(1.0, 0.0, 0.0), 1e-5));
+    }
+
+    #[test]
+    fn test_wrap_angle() {
+        assert!((wrap_angle(1.5 * PI) + 0.5 * PI).abs() < 1e-5);
+        assert!((wrap_angle(-1.5 * PI) - 0.5 * PI).abs() < 1e-5);
+    }
+}
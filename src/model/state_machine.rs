@@ -0,0 +1,584 @@
+use crate::model::blend_space::{BlendSample, BlendSpace};
+use crate::model::pose::Pose;
+use crate::model::Modelv2;
+use crate::playback::{CrossFade, Playhead, WrapMode};
+use anyhow::{Context, Result};
+use glam::Vec2;
+use serde::Deserialize;
+use std::path::Path;
+
+/// State machine as written in a `.states.json` file, states and parameters are referenced by name
+#[derive(Deserialize, Debug)]
+pub struct StateMachineDesc {
+    pub initial: String,
+    #[serde(default)]
+    pub parameters: Vec<ParameterDesc>,
+    pub states: Vec<StateDesc>,
+    #[serde(default)]
+    pub transitions: Vec<TransitionDesc>,
+    #[serde(default)]
+    pub blend_spaces: Vec<BlendSpaceDesc>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ParameterDesc {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ParameterKind,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParameterKind {
+    Float,
+    Bool,
+    /// Bool reset once a transition uses it
+    Trigger,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StateDesc {
+    pub name: String,
+    /// Name of the blend space or the animation played in the state
+    pub animation: String,
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    #[serde(default)]
+    pub wrap_mode: WrapMode,
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BlendSpaceDesc {
+    pub name: String,
+    /// Float parameters giving the position in the blend space, one per dimension
+    pub parameters: Vec<String>,
+    pub samples: Vec<BlendSampleDesc>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BlendSampleDesc {
+    pub animation: String,
+    /// One value per dimension
+    pub position: Vec<f32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TransitionDesc {
+    /// Name of the source state, `*` for any state
+    pub from: String,
+    pub to: String,
+    /// Cross-fade duration in seconds
+    #[serde(default)]
+    pub duration: f32,
+    /// Fraction of the source animation that should be played before leaving it
+    pub exit_time: Option<f32>,
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case", tag = "op")]
+pub enum Condition {
+    Greater { parameter: String, value: f32 },
+    Less { parameter: String, value: f32 },
+    True { parameter: String },
+    False { parameter: String },
+    Trigger { parameter: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParameterValue {
+    Float(f32),
+    Bool(bool),
+    Trigger(bool),
+}
+
+pub struct Parameter {
+    pub name: String,
+    pub value: ParameterValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    Animation(usize),
+    /// Blend space played by phase, its playhead goes from 0.0 to 1.0
+    BlendSpace(usize),
+}
+
+pub struct State {
+    pub name: String,
+    pub motion: Motion,
+    /// Length of the playhead of the state
+    pub duration: f32,
+    pub speed: f32,
+    pub wrap_mode: WrapMode,
+}
+
+/// Condition with its parameter resolved to an index
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ResolvedCondition {
+    Greater(usize, f32),
+    Less(usize, f32),
+    True(usize),
+    False(usize),
+    Trigger(usize),
+}
+
+pub struct Transition {
+    /// None for a transition from any state
+    pub from: Option<usize>,
+    pub to: usize,
+    pub duration: f32,
+    pub exit_time: Option<f32>,
+    conditions: Vec<ResolvedCondition>,
+}
+
+/// Transition being played, the source state keeps playing while it fades out
+pub struct ActiveTransition {
+    pub transition: usize,
+    pub from_state: usize,
+    pub fade: CrossFade,
+}
+
+/// Runtime of a state machine, it picks the animations to play from its parameters
+pub struct Animator {
+    pub states: Vec<State>,
+    pub blend_spaces: Vec<BlendSpace>,
+    /// Parameters giving the position of each blend space
+    blend_space_parameters: Vec<Vec<usize>>,
+    pub transitions: Vec<Transition>,
+    pub parameters: Vec<Parameter>,
+    pub current_state: usize,
+    pub playhead: Playhead,
+    pub active_transition: Option<ActiveTransition>,
+}
+
+impl Animator {
+    /// Load the state machine file and bind its states to the animations of the model
+    pub fn load(path: &Path, model: &Modelv2) -> Result<Self> {
+        let file =
+            std::fs::read_to_string(path).with_context(|| format!("Should be able to read {}", path.display()))?;
+        let desc: StateMachineDesc = serde_json::from_str(&file)
+            .with_context(|| format!("Should be a valid state machine {}", path.display()))?;
+        Self::new(&desc, model)
+    }
+
+    pub fn new(desc: &StateMachineDesc, model: &Modelv2) -> Result<Self> {
+        let animations = model.animations();
+        let find_animation = |name: &str| {
+            animations
+                .iter()
+                .position(|a| a.name == name)
+                .with_context(|| format!("Should find animation {}", name))
+        };
+        let find_parameter = |name: &str, kind: ParameterKind| {
+            desc.parameters
+                .iter()
+                .position(|parameter| parameter.name == name && parameter.kind == kind)
+                .with_context(|| format!("Should find parameter {} of type {:?}", name, kind))
+        };
+
+        let mut blend_spaces = Vec::new();
+        let mut blend_space_parameters = Vec::new();
+        for blend_space in desc.blend_spaces.iter() {
+            let dimensions = blend_space.parameters.len();
+            if !(1..=2).contains(&dimensions) {
+                return Err(anyhow::anyhow!(
+                    "Blend space {} should have one or two parameters",
+                    blend_space.name
+                ));
+            }
+            let mut samples = Vec::new();
+            for sample in blend_space.samples.iter() {
+                if sample.position.len() != dimensions {
+                    return Err(anyhow::anyhow!(
+                        "Samples of blend space {} should have {} values",
+                        blend_space.name,
+                        dimensions
+                    ));
+                }
+                let position = Vec2::new(sample.position[0], sample.position.get(1).copied().unwrap_or(0.0));
+                if samples.iter().any(|other: &BlendSample| other.position == position) {
+                    return Err(anyhow::anyhow!(
+                        "Samples of blend space {} should not share the position {:?}",
+                        blend_space.name,
+                        sample.position
+                    ));
+                }
+                let animation = find_animation(&sample.animation)?;
+                samples.push(BlendSample {
+                    animation,
+                    duration: animations[animation].duration(),
+                    position,
+                });
+            }
+            blend_spaces.push(BlendSpace::new(blend_space.name.clone(), samples, dimensions == 2));
+            blend_space_parameters.push(
+                blend_space
+                    .parameters
+                    .iter()
+                    .map(|parameter| find_parameter(parameter, ParameterKind::Float))
+                    .collect::<Result<Vec<usize>>>()?,
+            );
+        }
+
+        let mut states = Vec::new();
+        for state in desc.states.iter() {
+            let (motion, duration) = match blend_spaces.iter().position(|b| b.name == state.animation) {
+                Some(blend_space) => (Motion::BlendSpace(blend_space), 1.0),
+                None => {
+                    let animation = find_animation(&state.animation)
+                        .with_context(|| format!("Should find the animation of state {}", state.name))?;
+                    (Motion::Animation(animation), animations[animation].duration())
+                }
+            };
+            states.push(State {
+                name: state.name.clone(),
+                motion,
+                duration,
+                speed: state.speed,
+                wrap_mode: state.wrap_mode,
+            });
+        }
+
+        let parameters: Vec<Parameter> = desc
+            .parameters
+            .iter()
+            .map(|parameter| Parameter {
+                name: parameter.name.clone(),
+                value: match parameter.kind {
+                    ParameterKind::Float => ParameterValue::Float(0.0),
+                    ParameterKind::Bool => ParameterValue::Bool(false),
+                    ParameterKind::Trigger => ParameterValue::Trigger(false),
+                },
+            })
+            .collect();
+
+        let find_state = |name: &str| {
+            states
+                .iter()
+                .position(|state| state.name == name)
+                .with_context(|| format!("Should find state {}", name))
+        };
+        let mut transitions = Vec::new();
+        for transition in desc.transitions.iter() {
+            let from = match transition.from.as_str() {
+                "*" => None,
+                name => Some(find_state(name)?),
+            };
+            let mut conditions = Vec::new();
+            for condition in transition.conditions.iter() {
+                let condition = match condition {
+                    Condition::Greater { parameter, value } => {
+                        ResolvedCondition::Greater(find_parameter(parameter, ParameterKind::Float)?, *value)
+                    }
+                    Condition::Less { parameter, value } => {
+                        ResolvedCondition::Less(find_parameter(parameter, ParameterKind::Float)?, *value)
+                    }
+                    Condition::True { parameter } => {
+                        ResolvedCondition::True(find_parameter(parameter, ParameterKind::Bool)?)
+                    }
+                    Condition::False { parameter } => {
+                        ResolvedCondition::False(find_parameter(parameter, ParameterKind::Bool)?)
+                    }
+                    Condition::Trigger { parameter } => {
+                        ResolvedCondition::Trigger(find_parameter(parameter, ParameterKind::Trigger)?)
+                    }
+                };
+                conditions.push(condition);
+            }
+            transitions.push(Transition {
+                from,
+                to: find_state(&transition.to)?,
+                duration: transition.duration,
+                exit_time: transition.exit_time,
+                conditions,
+            });
+        }
+
+        Ok(Self {
+            current_state: find_state(&desc.initial)?,
+            states,
+            blend_spaces,
+            blend_space_parameters,
+            transitions,
+            parameters,
+            playhead: Playhead::new(),
+            active_transition: None,
+        })
+    }
+
+    /// Advance the playheads by `dt` seconds scaled by `speed`, then take the first transition whose conditions pass.
+    /// Return the animation time swept by the current state, as (animation, from, to), to find the events crossed.
+    pub fn update(&mut self, dt: f32, speed: f32) -> Vec<(usize, f32, f32)> {
+        for (blend_space, parameters) in self.blend_spaces.iter_mut().zip(self.blend_space_parameters.iter()) {
+            let mut position = [0.0; 2];
+            for (value, parameter) in position.iter_mut().zip(parameters.iter()) {
+                if let ParameterValue::Float(parameter) = self.parameters[*parameter].value {
+                    *value = parameter;
+                }
+            }
+            blend_space.set_position(Vec2::from(position));
+        }
+
+        let delta = dt * speed;
+        let current_state = &self.states[self.current_state];
+        let sweeps = advance_state(&mut self.playhead, current_state, &self.blend_spaces, delta);
+
+        if let Some(active) = &mut self.active_transition {
+            let from_state = &self.states[active.from_state];
+            advance_state(&mut active.fade.from_playhead, from_state, &self.blend_spaces, delta);
+            if !active.fade.update(dt) {
+                self.active_transition = None;
+            }
+            // A transition plays until its end before the next one can start
+            return sweeps;
+        }
+
+        if let Some(transition) = self.find_transition() {
+            self.start_transition(transition);
+        }
+        sweeps
+    }
+
+    fn find_transition(&self) -> Option<usize> {
+        let state = &self.states[self.current_state];
+        let normalized_time = if state.duration > 0.0 {
+            self.playhead.time / state.duration
+        } else {
+            1.0
+        };
+
+        self.transitions.iter().position(|transition| {
+            let from_current = match transition.from {
+                Some(from) => from == self.current_state,
+                None => transition.to != self.current_state,
+            };
+            from_current
+                && transition
+                    .exit_time
+                    .is_none_or(|exit_time| normalized_time >= exit_time)
+                && transition.conditions.iter().all(|condition| self.check(condition))
+        })
+    }
+
+    fn check(&self, condition: &ResolvedCondition) -> bool {
+        let value = |index: usize| self.parameters[index].value;
+        match *condition {
+            ResolvedCondition::Greater(i, threshold) => matches!(value(i), ParameterValue::Float(v) if v > threshold),
+            ResolvedCondition::Less(i, threshold) => matches!(value(i), ParameterValue::Float(v) if v < threshold),
+            ResolvedCondition::True(i) => value(i) == ParameterValue::Bool(true),
+            ResolvedCondition::False(i) => value(i) == ParameterValue::Bool(false),
+            ResolvedCondition::Trigger(i) => value(i) == ParameterValue::Trigger(true),
+        }
+    }
+
+    fn start_transition(&mut self, index: usize) {
+        let transition = &self.transitions[index];
+        for condition in transition.conditions.iter() {
+            if let ResolvedCondition::Trigger(i) = condition {
+                self.parameters[*i].value = ParameterValue::Trigger(false);
+            }
+        }
+
+        let from_state = self.current_state;
+        let from_playhead = std::mem::take(&mut self.playhead);
+        self.active_transition = Some(ActiveTransition {
+            transition: index,
+            from_state,
+            fade: CrossFade::new(from_state, from_playhead, transition.duration),
+        });
+        self.current_state = transition.to;
+    }
+
+    /// Pose of the current state, blended with the state faded out during a transition
+    pub fn sample_pose(&self, model: &Modelv2) -> Pose {
+        let pose = self.sample_state(model, self.current_state, self.playhead.time);
+        match &self.active_transition {
+            Some(active) => {
+                let mut from_pose = self.sample_state(model, active.from_state, active.fade.from_playhead.time);
+                from_pose.blend(&pose, active.fade.weight());
+                from_pose
+            }
+            None => pose,
+        }
+    }
+
+    /// Animation of the current state, the one with the most weight for a blend space
+    pub fn current_animation(&self) -> Option<usize> {
+        match self.states[self.current_state].motion {
+            Motion::Animation(animation) => Some(animation),
+            Motion::BlendSpace(blend_space) => self.blend_spaces[blend_space].main_sample().map(|s| s.animation),
+        }
+    }
+
+    fn sample_state(&self, model: &Modelv2, state: usize, time: f32) -> Pose {
+        match self.states[state].motion {
+            Motion::Animation(animation) => model.sample_pose(animation, time),
+            Motion::BlendSpace(blend_space) => self.blend_spaces[blend_space].sample_pose(model, time),
+        }
+    }
+}
+
+/// Advance the playhead of a state, a state played once holds its last frame. The playhead of a blend space moves
+/// by its phase, the time swept is the one of its animation with the most weight.
+fn advance_state(
+    playhead: &mut Playhead, state: &State, blend_spaces: &[BlendSpace], delta: f32,
+) -> Vec<(usize, f32, f32)> {
+    if state.wrap_mode == WrapMode::Once && playhead.finished {
+        return Vec::new();
+    }
+    match state.motion {
+        Motion::Animation(animation) => playhead
+            .advance(delta * state.speed, state.duration, state.wrap_mode)
+            .played
+            .into_iter()
+            .map(|(from, to)| (animation, from, to))
+            .collect(),
+        Motion::BlendSpace(blend_space) => {
+            let blend_space = &blend_spaces[blend_space];
+            let cycle = blend_space.duration();
+            let delta = if cycle > 0.0 { delta / cycle } else { 0.0 };
+            let sweeps = playhead.advance(delta * state.speed, state.duration, state.wrap_mode);
+            let Some(sample) = blend_space.main_sample() else {
+                return Vec::new();
+            };
+            sweeps
+                .played
+                .into_iter()
+                .map(|(from, to)| (sample.animation, from * sample.duration, to * sample.duration))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn animator() -> Animator {
+        let model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let desc: StateMachineDesc = serde_json::from_str(
+            r#"{
+                "initial": "Idle",
+                "parameters": [
+                    { "name": "speed", "type": "float" },
+                    { "name": "punch", "type": "trigger" }
+                ],
+                "states": [
+                    { "name": "Idle", "animation": "Idle" },
+                    { "name": "Walk", "animation": "Walking" },
+                    { "name": "Punch", "animation": "Punch", "wrap_mode": "once" }
+                ],
+                "transitions": [
+                    { "from": "Idle", "to": "Walk", "duration": 0.5,
+                      "conditions": [{ "op": "greater", "parameter": "speed", "value": 0.1 }] },
+                    { "from": "Walk", "to": "Idle", "duration": 0.5,
+                      "conditions": [{ "op": "less", "parameter": "speed", "value": 0.1 }] },
+                    { "from": "*", "to": "Punch", "duration": 0.1,
+                      "conditions": [{ "op": "trigger", "parameter": "punch" }] },
+                    { "from": "Punch", "to": "Idle", "duration": 0.2, "exit_time": 1.0 }
+                ]
+            }"#,
+        )
+        .unwrap();
+        Animator::new(&desc, &model).unwrap()
+    }
+
+    #[test]
+    fn test_conditions() {
+        let mut animator = animator();
+        animator.update(0.1, 1.0);
+        assert_eq!(animator.current_state, 0);
+
+        animator.parameters[0].value = ParameterValue::Float(1.0);
+        animator.update(0.1, 1.0);
+        assert_eq!(animator.current_state, 1);
+        let active = animator.active_transition.as_ref().unwrap();
+        assert_eq!(active.from_state, 0);
+
+        // The transition ends after its duration
+        animator.update(0.25, 1.0);
+        assert!(animator.active_transition.is_some());
+        animator.update(0.3, 1.0);
+        assert!(animator.active_transition.is_none());
+
+        animator.parameters[0].value = ParameterValue::Float(0.0);
+        animator.update(0.1, 1.0);
+        assert_eq!(animator.current_state, 0);
+    }
+
+    #[test]
+    fn test_trigger_and_exit_time() {
+        let mut animator = animator();
+        animator.parameters[1].value = ParameterValue::Trigger(true);
+        animator.update(0.1, 1.0);
+        assert_eq!(animator.current_state, 2);
+        // The trigger is consumed by the transition
+        assert_eq!(animator.parameters[1].value, ParameterValue::Trigger(false));
+
+        animator.update(0.2, 1.0);
+        assert_eq!(animator.current_state, 2);
+        let duration = animator.states[2].duration;
+        animator.update(duration, 1.0);
+        animator.update(0.0, 1.0);
+        assert_eq!(animator.current_state, 0);
+    }
+
+    #[test]
+    fn test_unknown_names() {
+        let model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let desc: StateMachineDesc =
+            serde_json::from_str(r#"{ "initial": "Idle", "states": [{ "name": "Idle", "animation": "Missing" }] }"#)
+                .unwrap();
+        assert!(Animator::new(&desc, &model).is_err());
+    }
+
+    #[test]
+    fn test_coincident_blend_samples() {
+        let model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let desc: StateMachineDesc = serde_json::from_str(
+            r#"{
+                "initial": "Move",
+                "parameters": [{ "name": "x", "type": "float" }, { "name": "y", "type": "float" }],
+                "blend_spaces": [{
+                    "name": "Move",
+                    "parameters": ["x", "y"],
+                    "samples": [
+                        { "animation": "Idle", "position": [0.0, 0.0] },
+                        { "animation": "Walking", "position": [0.0, 1.0] },
+                        { "animation": "Running", "position": [0.0, 1.0] }
+                    ]
+                }],
+                "states": [{ "name": "Move", "animation": "Move" }]
+            }"#,
+        )
+        .unwrap();
+        let error = Animator::new(&desc, &model).err().unwrap();
+        assert!(error.to_string().contains("should not share the position"), "{}", error);
+    }
+
+    #[test]
+    fn test_blend_space_state() {
+        let path = Path::new("rsc").join("Woman.states.json");
+        let model = Modelv2::load(&Path::new("rsc").join("Woman.gltf")).unwrap();
+        let mut animator = Animator::load(&path, &model).unwrap();
+        assert_eq!(animator.states[0].motion, Motion::BlendSpace(0));
+
+        animator.parameters[0].value = ParameterValue::Float(0.7);
+        animator.update(0.0, 1.0);
+        let blend_space = &animator.blend_spaces[0];
+        assert!((blend_space.weights[1] - 0.5).abs() < 1e-5);
+        assert!((blend_space.weights[2] - 0.5).abs() < 1e-5);
+
+        // The phase goes around once per blended cycle
+        let cycle = blend_space.duration();
+        animator.update(cycle / 4.0, 1.0);
+        assert!((animator.playhead.time - 0.25).abs() < 1e-4);
+        animator.sample_pose(&model);
+    }
+}
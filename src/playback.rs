@@ -0,0 +1,301 @@
+use serde::Deserialize;
+
+/// What happens when the playback reaches an end of a clip
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapMode {
+    /// Stop on the end and hold the pose, playing again restarts the clip
+    Once,
+    #[default]
+    Loop,
+    /// Play the clip back and forth
+    PingPong,
+    /// Stay on the end while the time keeps going, reversing the speed plays the clip back right away
+    Clamp,
+}
+
+impl WrapMode {
+    pub const ALL: [WrapMode; 4] = [WrapMode::Once, WrapMode::Loop, WrapMode::PingPong, WrapMode::Clamp];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WrapMode::Once => "Once",
+            WrapMode::Loop => "Loop",
+            WrapMode::PingPong => "Ping-pong",
+            WrapMode::Clamp => "Clamp",
+        }
+    }
+}
+
+/// Maximum number of times the clip ends are reached in a single advance
+const MAX_WRAPS: usize = 16;
+
+/// Clip time covered by an advance of a playhead, as (from, to) intervals
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sweeps {
+    /// Change of the time since the last advance when it was scrubbed, it crosses events but does not move the model
+    pub scrubbed: Option<(f32, f32)>,
+    /// Intervals played, in order
+    pub played: Vec<(f32, f32)>,
+}
+
+impl Sweeps {
+    /// The scrubbed interval then the played ones
+    pub fn all(&self) -> Vec<(f32, f32)> {
+        self.scrubbed.iter().chain(&self.played).copied().collect()
+    }
+}
+
+/// Current time in a clip
+#[derive(Clone, Debug)]
+pub struct Playhead {
+    pub time: f32,
+    /// -1.0 when a ping-pong is on its way back
+    direction: f32,
+    /// Set when a clip played once reaches its end
+    pub finished: bool,
+    /// Time at the end of the last advance, the time moved since then when it was scrubbed
+    last_time: f32,
+}
+
+impl Default for Playhead {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Playhead {
+    pub fn new() -> Self {
+        Self {
+            time: 0.0,
+            direction: 1.0,
+            finished: false,
+            last_time: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Jump to `time` without sweeping the clip up to it, unlike scrubbing
+    pub fn seek(&mut self, time: f32) {
+        self.time = time;
+        self.last_time = time;
+    }
+
+    /// Move the time by `delta` seconds, a negative delta plays the clip in reverse. Return the intervals of clip
+    /// time played, and the change of the time since the last advance when it is scrubbed.
+    pub fn advance(&mut self, delta: f32, duration: f32, wrap_mode: WrapMode) -> Sweeps {
+        let mut sweeps = Sweeps::default();
+        if duration <= 0.0 {
+            self.time = 0.0;
+            self.last_time = 0.0;
+            return sweeps;
+        }
+        // The clip may have changed since the last advance
+        self.time = self.time.clamp(0.0, duration);
+        if self.time != self.last_time {
+            sweeps.scrubbed = Some((self.last_time.clamp(0.0, duration), self.time));
+        }
+
+        if wrap_mode != WrapMode::PingPong {
+            self.direction = 1.0;
+        }
+        if wrap_mode == WrapMode::Once && self.finished && delta != 0.0 {
+            self.finished = false;
+            if delta > 0.0 && self.time >= duration {
+                self.time = 0.0;
+            } else if delta < 0.0 && self.time <= 0.0 {
+                self.time = duration;
+            }
+        }
+
+        let mut remaining = delta;
+        for _ in 0..MAX_WRAPS {
+            let target = self.time + remaining * self.direction;
+            if (0.0..=duration).contains(&target) {
+                sweeps.played.push((self.time, target));
+                self.time = target;
+                break;
+            }
+
+            let end = if target > duration { duration } else { 0.0 };
+            sweeps.played.push((self.time, end));
+            remaining -= (end - self.time) * self.direction;
+
+            match wrap_mode {
+                WrapMode::Once => {
+                    self.time = end;
+                    self.finished = true;
+                    break;
+                }
+                WrapMode::Loop => {
+                    self.time = duration - end;
+                }
+                WrapMode::PingPong => {
+                    self.time = end;
+                    self.direction = -self.direction;
+                }
+                WrapMode::Clamp => {
+                    self.time = end;
+                    break;
+                }
+            }
+        }
+
+        self.last_time = self.time;
+        sweeps
+    }
+}
+
+/// Fade out of a clip that keeps playing while the next one takes over
+#[derive(Clone, Debug)]
+pub struct CrossFade {
+    /// Animation, or state of a state machine, faded out
+    pub from: usize,
+    pub from_playhead: Playhead,
+    /// Fade the clip faded out was still in when this one started, it goes on under this one
+    pub previous: Option<Box<CrossFade>>,
+    elapsed: f32,
+    duration: f32,
+}
+
+impl CrossFade {
+    pub fn new(from: usize, from_playhead: Playhead, duration: f32) -> Self {
+        Self {
+            from,
+            from_playhead,
+            previous: None,
+            elapsed: 0.0,
+            duration,
+        }
+    }
+
+    /// Move the fade by `dt` seconds, return false once the fade is over
+    pub fn update(&mut self, dt: f32) -> bool {
+        self.elapsed += dt;
+        if let Some(previous) = &mut self.previous {
+            if !previous.update(dt) {
+                self.previous = None;
+            }
+        }
+        self.elapsed < self.duration
+    }
+
+    /// Weight of the clip faded in
+    pub fn weight(&self) -> f32 {
+        if self.duration <= 0.0 {
+            return 1.0;
+        }
+        (self.elapsed / self.duration).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_sweeps(sweeps: Sweeps, expected: &[(f32, f32)]) {
+        assert_eq!(sweeps.scrubbed, None);
+        let sweeps = sweeps.played;
+        assert_eq!(sweeps.len(), expected.len(), "{:?} != {:?}", sweeps, expected);
+        for (sweep, expected) in sweeps.iter().zip(expected) {
+            assert!(
+                (sweep.0 - expected.0).abs() < 1e-5 && (sweep.1 - expected.1).abs() < 1e-5,
+                "{:?} != {:?}",
+                sweeps,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_loop() {
+        let mut playhead = Playhead::new();
+        playhead.seek(0.8);
+
+        assert_sweeps(playhead.advance(0.5, 1.0, WrapMode::Loop), &[(0.8, 1.0), (0.0, 0.3)]);
+        assert!((playhead.time - 0.3).abs() < 1e-5);
+
+        assert_sweeps(playhead.advance(-0.5, 1.0, WrapMode::Loop), &[(0.3, 0.0), (1.0, 0.8)]);
+        assert!((playhead.time - 0.8).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_once() {
+        let mut playhead = Playhead::new();
+        playhead.seek(0.8);
+
+        assert_sweeps(playhead.advance(0.5, 1.0, WrapMode::Once), &[(0.8, 1.0)]);
+        assert_eq!(playhead.time, 1.0);
+        assert!(playhead.finished);
+
+        // Playing again starts over
+        assert_sweeps(playhead.advance(0.5, 1.0, WrapMode::Once), &[(0.0, 0.5)]);
+        assert!(!playhead.finished);
+    }
+
+    #[test]
+    fn test_ping_pong() {
+        let mut playhead = Playhead::new();
+        playhead.seek(0.8);
+
+        assert_sweeps(
+            playhead.advance(0.5, 1.0, WrapMode::PingPong),
+            &[(0.8, 1.0), (1.0, 0.7)],
+        );
+        assert_sweeps(playhead.advance(0.5, 1.0, WrapMode::PingPong), &[(0.7, 0.2)]);
+        assert_sweeps(
+            playhead.advance(0.5, 1.0, WrapMode::PingPong),
+            &[(0.2, 0.0), (0.0, 0.3)],
+        );
+        assert!((playhead.time - 0.3).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_clamp() {
+        let mut playhead = Playhead::new();
+        playhead.seek(0.8);
+
+        assert_sweeps(playhead.advance(0.5, 1.0, WrapMode::Clamp), &[(0.8, 1.0)]);
+        assert_sweeps(playhead.advance(0.5, 1.0, WrapMode::Clamp), &[(1.0, 1.0)]);
+        assert!(!playhead.finished);
+        assert_sweeps(playhead.advance(-0.25, 1.0, WrapMode::Clamp), &[(1.0, 0.75)]);
+    }
+
+    #[test]
+    fn test_cross_fade() {
+        let mut fade = CrossFade::new(0, Playhead::new(), 0.5);
+        assert_eq!(fade.weight(), 0.0);
+        assert!(fade.update(0.25));
+        assert_eq!(fade.weight(), 0.5);
+        assert!(!fade.update(0.5));
+        assert_eq!(fade.weight(), 1.0);
+
+        assert_eq!(CrossFade::new(0, Playhead::new(), 0.0).weight(), 1.0);
+    }
+
+    #[test]
+    fn test_nested_cross_fade() {
+        let mut fade = CrossFade::new(1, Playhead::new(), 0.5);
+        fade.previous = Some(Box::new(CrossFade::new(0, Playhead::new(), 0.2)));
+        assert!(fade.update(0.1));
+        assert_eq!(fade.previous.as_ref().unwrap().weight(), 0.5);
+        // The fade interrupted ends first and is dropped
+        assert!(fade.update(0.1));
+        assert!(fade.previous.is_none());
+        assert!((fade.weight() - 0.4).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_scrub() {
+        let mut playhead = Playhead::new();
+        playhead.advance(0.5, 1.0, WrapMode::Loop);
+        playhead.time = 0.2;
+        let sweeps = playhead.advance(0.0, 1.0, WrapMode::Loop);
+        assert_eq!(sweeps.scrubbed, Some((0.5, 0.2)));
+        assert_eq!(sweeps.all(), vec![(0.5, 0.2), (0.2, 0.2)]);
+        assert_sweeps(playhead.advance(0.1, 1.0, WrapMode::Loop), &[(0.2, 0.3)]);
+    }
+}
@@ -10,12 +10,22 @@ var<uniform> camera: CameraUniform;
 @group(3) @binding(0)
 var<storage, read> joints: array<mat4x4<f32>>;
 
+struct MorphDelta {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+};
+
+@group(3) @binding(1)
+var<storage, read> morph_deltas: array<MorphDelta>;
+@group(3) @binding(2)
+var<storage, read> morph_weights: array<f32>;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) tex_coords: vec2<f32>,
-    @location(3) affected_joints: vec4<u32>,
-    @location(4) joint_weights: vec4<f32>,
+    // First delta, first weight and number of morph targets
+    @location(9) morph_targets: vec3<u32>,
 };
 
 struct VertexOutput {
@@ -33,9 +43,14 @@ struct ModelMat{
 }
 
 
+fn linearJoint(joint: u32) -> mat4x4<f32> {
+    return joints[joint];
+}
+
 @vertex
 fn vs_main(
     model: VertexInput,
+    influences: Influences,
     model_mat: ModelMat,
 ) -> VertexOutput {
     var out: VertexOutput;
@@ -46,21 +61,24 @@ fn vs_main(
         model_mat.model_matrix_3,
     );
 
-    let normal_matrix = mat3x3<f32>(
-        model_mat.model_matrix_0.xyz,
-        model_mat.model_matrix_1.xyz,
-        model_mat.model_matrix_2.xyz,
-    );
-    
-    let skinMat = model.joint_weights.x * joints[model.affected_joints.x] +
-        		model.joint_weights.y * joints[model.affected_joints.y] +
-        		model.joint_weights.z * joints[model.affected_joints.z] +
-        		model.joint_weights.w * joints[model.affected_joints.w];
+    var position = model.position;
+    var normal = model.normal;
+    for (var i = 0u; i < model.morph_targets.z; i++) {
+        let weight = morph_weights[model.morph_targets.y + i];
+        let delta = morph_deltas[model.morph_targets.x + i];
+        position += weight * delta.position.xyz;
+        normal += weight * delta.normal.xyz;
+    }
+
+    let skinMat = linearSkinMat(influences);
 
-    let world_position: vec4<f32> = model_matrix * skinMat  * vec4<f32>(model.position, 1.0);
+    let skinned_matrix = model_matrix * skinMat;
+    let world_position: vec4<f32> = skinned_matrix * vec4<f32>(position, 1.0);
     out.clip_position = camera.view_proj * world_position;
     out.tex_coords = model.tex_coords;
-    out.world_normal = normalize(normal_matrix * model.normal);
+    // Normals go through the same transform as the positions
+    let normal_matrix = inverse_transpose(mat3x3<f32>(skinned_matrix[0].xyz, skinned_matrix[1].xyz, skinned_matrix[2].xyz));
+    out.world_normal = normalize_or_zero(normal_matrix * normal);
     out.world_position = world_position.xyz;
     return out;
 }
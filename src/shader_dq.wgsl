@@ -7,15 +7,32 @@ struct CameraUniform {
 @group(1) @binding(0)
 var<uniform> camera: CameraUniform;
 
+// Rigid transform of a joint as a dual quaternion, and the scale and shear applied before it
+struct DualQuatJoint {
+    real: vec4<f32>,
+    dual: vec4<f32>,
+    scale: mat3x3<f32>,
+};
+
 @group(3) @binding(0)
-var<storage, read> joints: array<mat2x4<f32>>;
+var<storage, read> joints: array<DualQuatJoint>;
+
+struct MorphDelta {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+};
+
+@group(3) @binding(1)
+var<storage, read> morph_deltas: array<MorphDelta>;
+@group(3) @binding(2)
+var<storage, read> morph_weights: array<f32>;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) normal: vec3<f32>,
     @location(2) tex_coords: vec2<f32>,
-    @location(3) affected_joints: vec4<u32>,
-    @location(4) joint_weights: vec4<f32>,
+    // First delta, first weight and number of morph targets
+    @location(9) morph_targets: vec3<u32>,
 };
 
 struct VertexOutput {
@@ -32,54 +49,18 @@ struct ModelMat{
    @location(8) model_matrix_3: vec4<f32>,
 }
 
-fn getJointTransform(affected_joints: vec4<u32>, weights: vec4<f32>) -> mat2x4<f32> {
-    let dq0:mat2x4<f32> = joints[affected_joints.x];
-    let dq1:mat2x4<f32> = joints[affected_joints.y];
-    let dq2:mat2x4<f32> = joints[affected_joints.z];
-    let dq3:mat2x4<f32> = joints[affected_joints.w];
-
-    let wx = weights.x;
-    let wy = weights.y * sign(dot(dq0[0], dq1[0]));
-    let wz = weights.z * sign(dot(dq0[0], dq2[0]));
-    let ww = weights.w * sign(dot(dq0[0], dq3[0]));
-
-    let result: mat2x4<f32> = wx * dq0 + wy * dq1 + wz * dq2 + ww * dq3;
-
-    let norm = length(result[0]);
-    return result * (1 / norm);
+fn dualQuatJoint(joint: u32) -> mat2x4<f32> {
+    return mat2x4<f32>(joints[joint].real, joints[joint].dual);
 }
 
-fn getskinMat(model: VertexInput) -> mat4x4<f32> {
-    let bone:mat2x4<f32> = getJointTransform(model.affected_joints, model.joint_weights);
-    let r = bone[0];
-    let t = bone[1];
-
-    return mat4x4<f32>(
-        1.0 - (2.0 * r.y * r.y) - (2.0 * r.z * r.z),
-            (2.0 * r.x * r.y) + (2.0 * r.w * r.z),
-            (2.0 * r.x * r.z) - (2.0 * r.w * r.y),
-        0.0,
-
-            (2.0 * r.x * r.y) - (2.0 * r.w * r.z),
-        1.0 - (2.0 * r.x * r.x) - (2.0 * r.z * r.z),
-            (2.0 * r.y * r.z) + (2.0 * r.w * r.x),
-        0.0,
-
-            (2.0 * r.x * r.z) + (2.0 * r.w * r.y),
-            (2.0 * r.y * r.z) - (2.0 * r.w * r.x),
-        1.0 - (2.0 * r.x * r.x) - (2.0 * r.y * r.y),
-        0.0,
-
-        2.0 * (-t.w * r.x + t.x * r.w - t.y * r.z + t.z * r.y),
-        2.0 * (-t.w * r.y + t.x * r.z + t.y * r.w - t.z * r.x),
-        2.0 * (-t.w * r.z - t.x * r.y + t.y * r.x + t.z * r.w),
-        1);
+fn scaleJoint(joint: u32) -> mat3x3<f32> {
+    return joints[joint].scale;
 }
 
-
 @vertex
 fn vs_main(
     model: VertexInput,
+    influences: Influences,
     model_mat: ModelMat,
 ) -> VertexOutput {
     var out: VertexOutput;
@@ -90,18 +71,24 @@ fn vs_main(
         model_mat.model_matrix_3,
     );
 
-    let normal_matrix = mat3x3<f32>(
-        model_mat.model_matrix_0.xyz,
-        model_mat.model_matrix_1.xyz,
-        model_mat.model_matrix_2.xyz,
-    );
-    
-    let skinMat = getskinMat(model);
+    var position = model.position;
+    var normal = model.normal;
+    for (var i = 0u; i < model.morph_targets.z; i++) {
+        let weight = morph_weights[model.morph_targets.y + i];
+        let delta = morph_deltas[model.morph_targets.x + i];
+        position += weight * delta.position.xyz;
+        normal += weight * delta.normal.xyz;
+    }
+
+    let skinMat = dualQuatSkinMat(influences);
 
-    let world_position: vec4<f32> = model_matrix * skinMat  * vec4<f32>(model.position, 1.0);
+    let skinned_matrix = model_matrix * skinMat;
+    let world_position: vec4<f32> = skinned_matrix * vec4<f32>(position, 1.0);
     out.clip_position = camera.view_proj * world_position;
     out.tex_coords = model.tex_coords;
-    out.world_normal = normalize(normal_matrix * model.normal);
+    // Normals go through the same transform as the positions
+    let normal_matrix = inverse_transpose(mat3x3<f32>(skinned_matrix[0].xyz, skinned_matrix[1].xyz, skinned_matrix[2].xyz));
+    out.world_normal = normalize_or_zero(normal_matrix * normal);
     out.world_position = world_position.xyz;
     return out;
 }
@@ -0,0 +1,117 @@
+use crate::model::Modelv2;
+use crate::vertex::InfluenceLayout;
+use wgpu::{BindGroupLayout, CommandEncoder, ComputePipeline, Device};
+
+/// Vertices skinned by one invocation group of the compute shader
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Compute pass skinning the vertices of a model once per frame, for the render passes to draw as static geometry
+pub struct SkinningPass {
+    bind_group_layout: BindGroupLayout,
+    linear_pipeline: ComputePipeline,
+    dual_quat_pipeline: ComputePipeline,
+}
+
+impl SkinningPass {
+    /// Pipelines reading the vertices in the `influence_layout` of the model
+    pub fn new(device: &Device, influence_layout: InfluenceLayout) -> Self {
+        let storage = |binding: u32, read_only: bool| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                // Vertices
+                storage(0, true),
+                // Joints
+                storage(1, true),
+                // Morph target deltas
+                storage(2, true),
+                // Morph target weights
+                storage(3, true),
+                // Skinned vertices
+                storage(4, false),
+            ],
+            label: Some("skinning_bind_group_layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("skinning.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                [
+                    include_str!("common.wgsl"),
+                    influence_layout.wgsl(),
+                    include_str!("linear_blend.wgsl"),
+                    include_str!("dual_quat_blend.wgsl"),
+                    include_str!("skinning.wgsl"),
+                ]
+                .concat()
+                .into(),
+            ),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skinning Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label: &str, entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+        let linear_pipeline = pipeline("Skinning Pipeline", "skin_linear");
+        let dual_quat_pipeline = pipeline("Skinning Pipeline DQ", "skin_dual_quat");
+
+        Self {
+            bind_group_layout,
+            linear_pipeline,
+            dual_quat_pipeline,
+        }
+    }
+
+    pub fn bind_group_layout(&self) -> &BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// Skin the vertices of the model with the joints of its last `render_animation`
+    pub fn dispatch(&self, encoder: &mut CommandEncoder, model: &Modelv2, double_quat_joints_render: bool) {
+        let Some(bind_group) = model.skinning_bind_group() else {
+            return;
+        };
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Skinning Pass"),
+            timestamp_writes: None,
+        });
+        if double_quat_joints_render {
+            compute_pass.set_pipeline(&self.dual_quat_pipeline);
+        } else {
+            compute_pass.set_pipeline(&self.linear_pipeline);
+        }
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.dispatch_workgroups(model.vertex_count().div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vertex::{CompactVertex, SkinnedVertex, Vertex};
+
+    #[test]
+    fn test_shader_vertex_layout() {
+        // The compute shader reads the vertices and writes the skinned ones word by word
+        assert_eq!(size_of::<Vertex>(), 27 * 4);
+        assert_eq!(size_of::<CompactVertex>(), 19 * 4);
+        assert_eq!(size_of::<SkinnedVertex>(), 8 * 4);
+    }
+}
@@ -0,0 +1,119 @@
+// Compute shader skinning the vertices once per frame, the render passes then draw the result as static geometry
+
+// Vertices as loaded, read word by word since their vec3 fields are packed
+@group(0) @binding(0)
+var<storage, read> vertices: array<u32>;
+
+// 4 columns per joint for the linear skinning, a dual quaternion then 3 columns of scale for the dual quaternion one
+@group(0) @binding(1)
+var<storage, read> joints: array<vec4<f32>>;
+
+struct MorphDelta {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+};
+
+@group(0) @binding(2)
+var<storage, read> morph_deltas: array<MorphDelta>;
+@group(0) @binding(3)
+var<storage, read> morph_weights: array<f32>;
+
+// Position, normal and texture coordinates of each vertex
+@group(0) @binding(4)
+var<storage, read_write> skinned_vertices: array<f32>;
+
+// Words of a vertex: position, normal, tex_coords, affected_joints and joint_weights of each set, morph_targets
+const VERTEX_WORDS: u32 = 11u + 8u * INFLUENCE_SETS;
+const SKINNED_VERTEX_WORDS: u32 = 8u;
+
+struct VertexInput {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    tex_coords: vec2<f32>,
+    influences: Influences,
+    // First delta, first weight and number of morph targets
+    morph_targets: vec3<u32>,
+};
+
+fn read_f32(index: u32) -> f32 {
+    return bitcast<f32>(vertices[index]);
+}
+
+fn read_vertex(index: u32) -> VertexInput {
+    let b = index * VERTEX_WORDS;
+    var vertex: VertexInput;
+    vertex.position = vec3<f32>(read_f32(b), read_f32(b + 1u), read_f32(b + 2u));
+    vertex.normal = vec3<f32>(read_f32(b + 3u), read_f32(b + 4u), read_f32(b + 5u));
+    vertex.tex_coords = vec2<f32>(read_f32(b + 6u), read_f32(b + 7u));
+    var affected_joints: array<vec4<u32>, 2>;
+    var joint_weights: array<vec4<f32>, 2>;
+    for (var influence_set = 0u; influence_set < INFLUENCE_SETS; influence_set++) {
+        let j = b + 8u + 4u * influence_set;
+        affected_joints[influence_set] = vec4<u32>(vertices[j], vertices[j + 1u], vertices[j + 2u], vertices[j + 3u]);
+        let w = b + 8u + 4u * (INFLUENCE_SETS + influence_set);
+        joint_weights[influence_set] = vec4<f32>(read_f32(w), read_f32(w + 1u), read_f32(w + 2u), read_f32(w + 3u));
+    }
+    vertex.influences = makeInfluences(affected_joints, joint_weights);
+    let m = b + 8u + 8u * INFLUENCE_SETS;
+    vertex.morph_targets = vec3<u32>(vertices[m], vertices[m + 1u], vertices[m + 2u]);
+
+    for (var i = 0u; i < vertex.morph_targets.z; i++) {
+        let weight = morph_weights[vertex.morph_targets.y + i];
+        let delta = morph_deltas[vertex.morph_targets.x + i];
+        vertex.position += weight * delta.position.xyz;
+        vertex.normal += weight * delta.normal.xyz;
+    }
+    return vertex;
+}
+
+fn write_vertex(index: u32, position: vec3<f32>, normal: vec3<f32>, tex_coords: vec2<f32>) {
+    let b = index * SKINNED_VERTEX_WORDS;
+    skinned_vertices[b] = position.x;
+    skinned_vertices[b + 1u] = position.y;
+    skinned_vertices[b + 2u] = position.z;
+    skinned_vertices[b + 3u] = normal.x;
+    skinned_vertices[b + 4u] = normal.y;
+    skinned_vertices[b + 5u] = normal.z;
+    skinned_vertices[b + 6u] = tex_coords.x;
+    skinned_vertices[b + 7u] = tex_coords.y;
+}
+
+fn skin(index: u32, vertex: VertexInput, skinMat: mat4x4<f32>) {
+    let position = skinMat * vec4<f32>(vertex.position, 1.0);
+    let normal_matrix = inverse_transpose(mat3x3<f32>(skinMat[0].xyz, skinMat[1].xyz, skinMat[2].xyz));
+    write_vertex(index, position.xyz, normalize_or_zero(normal_matrix * vertex.normal), vertex.tex_coords);
+}
+
+fn linearJoint(joint: u32) -> mat4x4<f32> {
+    let b = joint * 4u;
+    return mat4x4<f32>(joints[b], joints[b + 1u], joints[b + 2u], joints[b + 3u]);
+}
+
+@compute @workgroup_size(64)
+fn skin_linear(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= arrayLength(&vertices) / VERTEX_WORDS) {
+        return;
+    }
+    let vertex = read_vertex(id.x);
+    let skinMat = linearSkinMat(vertex.influences);
+    skin(id.x, vertex, skinMat);
+}
+
+fn dualQuatJoint(joint: u32) -> mat2x4<f32> {
+    return mat2x4<f32>(joints[joint * 5u], joints[joint * 5u + 1u]);
+}
+
+fn scaleJoint(joint: u32) -> mat3x3<f32> {
+    let b = joint * 5u + 2u;
+    return mat3x3<f32>(joints[b].xyz, joints[b + 1u].xyz, joints[b + 2u].xyz);
+}
+
+@compute @workgroup_size(64)
+fn skin_dual_quat(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= arrayLength(&vertices) / VERTEX_WORDS) {
+        return;
+    }
+    let vertex = read_vertex(id.x);
+    let skinMat = dualQuatSkinMat(vertex.influences);
+    skin(id.x, vertex, skinMat);
+}
@@ -1,12 +1,14 @@
 use crate::basic_object::renderer::BasicObjectRenderer;
 use crate::camera::{Camera, CameraMatBuffer};
 use crate::color::color_from_rgba_hex;
-use crate::data::UserDomain;
+use crate::data::{FiredEvent, IkHandle, SkinningMode, UserDomain};
 use crate::gui::EguiRenderer;
 use crate::light::LightBuffer;
-use crate::model::Modelv2;
+use crate::model::{Animator, IkChain, IkSolver, LookAt, LookAtJoint, Modelv2, RootMotionMode};
+use crate::playback::{Sweeps, WrapMode};
+use crate::skinning::SkinningPass;
 use crate::texture::Texture;
-use crate::vertex::Vertex;
+use crate::vertex::SkinnedVertex;
 use crate::{gui, texture};
 use egui_wgpu::wgpu::util::DeviceExt;
 use egui_wgpu::wgpu::Adapter;
@@ -15,8 +17,9 @@ use egui_winit::winit::dpi::PhysicalSize;
 use egui_winit::winit::event::{DeviceEvent, ElementState, KeyEvent, MouseButton, WindowEvent};
 use egui_winit::winit::keyboard::{KeyCode, PhysicalKey};
 use egui_winit::winit::window::Window;
-use glam::{vec3, Mat4};
-use log::info;
+use glam::{vec3, Mat4, Vec2, Vec3};
+use log::{info, warn};
+use std::path::Path;
 use std::sync::Arc;
 use std::time;
 use std::time::Duration;
@@ -32,8 +35,11 @@ pub struct State {
 
     render_pipeline: wgpu::RenderPipeline,
     render_pipeline_dq: wgpu::RenderPipeline,
+    /// Draws the vertices skinned by `skinning_pass`
+    render_pipeline_static: wgpu::RenderPipeline,
+    skinning_pass: SkinningPass,
 
-    woman_model: Modelv2,
+    model: Modelv2,
 
     depth_texture: Texture,
 
@@ -56,7 +62,7 @@ pub struct State {
 
 impl State {
     // Creating some of the wgpu types requires async code
-    pub async fn new(window: Arc<Window>) -> State {
+    pub async fn new(window: Arc<Window>, model_path: &Path) -> State {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -198,16 +204,40 @@ impl State {
         });
 
         let joints_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility: wgpu::ShaderStages::VERTEX,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Storage { read_only: true },
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
                 },
-                count: None,
-            }],
+                // Morph target deltas
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // Morph target weights
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
             label: Some("joints_bind_group_layout"),
         });
 
@@ -245,7 +275,25 @@ impl State {
             ],
         };
 
-        let shader = device.create_shader_module(wgpu::include_wgsl!("shader.wgsl"));
+        info!("Before loading model");
+
+        let mut model = Modelv2::load(model_path).unwrap();
+        // Only the vertex layout of the loaded model is built
+        let influence_layout = model.influence_layout();
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shader.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                [
+                    include_str!("common.wgsl"),
+                    influence_layout.wgsl(),
+                    include_str!("linear_blend.wgsl"),
+                    include_str!("shader.wgsl"),
+                ]
+                .concat()
+                .into(),
+            ),
+        });
 
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
@@ -264,7 +312,7 @@ impl State {
             vertex: wgpu::VertexState {
                 module: &shader,
                 entry_point: None,
-                buffers: &[Vertex::desc(), mat4_buffer_layout.clone()],
+                buffers: &[influence_layout.vertex_desc(), mat4_buffer_layout.clone()],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
             fragment: Some(wgpu::FragmentState {
@@ -305,14 +353,26 @@ impl State {
             cache: None,
         });
 
-        let shader_dq = device.create_shader_module(wgpu::include_wgsl!("shader_dq.wgsl"));
+        let shader_dq = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shader_dq.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                [
+                    include_str!("common.wgsl"),
+                    influence_layout.wgsl(),
+                    include_str!("dual_quat_blend.wgsl"),
+                    include_str!("shader_dq.wgsl"),
+                ]
+                .concat()
+                .into(),
+            ),
+        });
         let render_pipeline_dq = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline DQ"),
             layout: Some(&render_pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &shader_dq,
                 entry_point: None,
-                buffers: &[Vertex::desc(), mat4_buffer_layout],
+                buffers: &[influence_layout.vertex_desc(), mat4_buffer_layout.clone()],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
             fragment: Some(wgpu::FragmentState {
@@ -353,20 +413,172 @@ impl State {
             cache: None,
         });
 
-        info!("Before loading model");
+        let shader_static = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("shader_static.wgsl"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("common.wgsl"), include_str!("shader_static.wgsl")).into(),
+            ),
+        });
+        let static_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Static Render Pipeline Layout"),
+            bind_group_layouts: &[
+                &texture_bind_group_layout,
+                &camera_bind_group_layout,
+                &light_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline_static = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Render Pipeline Static"),
+            layout: Some(&static_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_static,
+                entry_point: None,
+                buffers: &[SkinnedVertex::desc(), mat4_buffer_layout],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_static,
+                entry_point: None,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+                polygon_mode: wgpu::PolygonMode::Fill,
+                // Requires Features::DEPTH_CLIP_CONTROL
+                unclipped_depth: false,
+                // Requires Features::CONSERVATIVE_RASTERIZATION
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: wgpu::TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
 
-        let mut woman_model = Modelv2::load_woman().unwrap();
-        woman_model.load_on_gpu(&device, &queue, &texture_bind_group_layout, &joints_bind_group_layout);
-        //let woman_model = Model::from_gltf(&device, &queue, &*PathBuf::from("rsc").join("duck").join("glTF").join("Duck.gltf"), &texture_bind_group_layout).unwrap();
-        //let woman_model = Model::from_gltf(&device, &queue, &*PathBuf::from("rsc").join("lantern").join("Lantern.gltf"), &texture_bind_group_layout).unwrap();
+        let skinning_pass = SkinningPass::new(&device, influence_layout);
+
+        model.load_on_gpu(
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+            &joints_bind_group_layout,
+            skinning_pass.bind_group_layout(),
+        );
 
         let egui_renderer = EguiRenderer::new(&device, config.format, None, 1, window.as_ref());
 
         let mut data = UserDomain::new();
         data.camera.aspect = (size.width as f32) / (size.height as f32);
         data.camera.update_vectors();
-        data.animations = woman_model.get_animation_names();
-        data.animations_duration = woman_model.animations().iter().map(|a| a.duration()).collect();
+        if !model.animations().is_empty() {
+            data.animations = model.get_animation_names();
+            data.animations_duration = model.animations().iter().map(|a| a.duration()).collect();
+            data.wrap_modes = vec![WrapMode::default(); model.animations().len()];
+            data.root_motion_modes = vec![RootMotionMode::default(); model.animations().len()];
+        }
+        data.animation_events = model
+            .animations()
+            .iter()
+            .map(|animation| animation.events.iter().map(|e| (e.name.clone(), e.time)).collect())
+            .collect();
+        (data.node_names, data.node_parents) = model.get_node_hierarchy();
+        data.influence_report = *model.influence_report();
+        let find_node = |name: &str| data.node_names.iter().position(|node| node == name);
+        // The presets below are for the skeletons using these names, the ones missing a node are skipped
+        let find_preset_node = |name: &str, preset: &str| {
+            let node = find_node(name);
+            if node.is_none() {
+                warn!("Skipping the {} preset, the model has no node {}", preset, name);
+            }
+            node
+        };
+        // Upper body
+        if find_preset_node("Spine1", "upper body mask").is_some() {
+            data.masks.extend(model.create_mask("Spine1"));
+        }
+        // Disabled, their targets start on the hands: an arm, and two hands holding a prop that share the spine
+        let presets = [
+            ("RightArm", "RightHand", IkSolver::Ccd),
+            ("Spine", "LeftHand", IkSolver::Fabrik),
+            ("Spine", "RightHand", IkSolver::Fabrik),
+            ("LeftUpLeg", "LeftFoot", IkSolver::TwoBone),
+        ];
+        let model_matrix = data.calculate_model_matrix();
+        for (root, effector, solver) in presets {
+            if let (Some(root), Some(effector)) = (
+                find_preset_node(root, "IK chain"),
+                find_preset_node(effector, "IK chain"),
+            ) {
+                let target = model.node_position(effector, model_matrix);
+                let mut chain = IkChain::new(Some(root), Some(effector), target, solver);
+                chain.enabled = false;
+                if solver == IkSolver::TwoBone {
+                    // In front of the middle joint, on the side it bends to
+                    let chain_nodes = model.ik_chain(root, effector).unwrap_or_default();
+                    if let [.., upper, middle, _] = chain_nodes[..] {
+                        let upper = model.node_position(upper, model_matrix);
+                        let middle = model.node_position(middle, model_matrix);
+                        let bend = (middle - (upper + target) / 2.0).normalize_or(Vec3::Z);
+                        chain.pole = Some(middle + bend * 0.5);
+                    }
+                    chain.softness = 0.05;
+                }
+                data.ik_chains.push(chain);
+            }
+        }
+        // Head turning with the neck and the upper spine, disabled, looking at the camera
+        let joints = [("Spine2", 0.2, 20.0_f32), ("Neck", 0.4, 30.0), ("Head", 1.0, 45.0)];
+        if let Some(nodes) = joints
+            .iter()
+            .map(|(name, _, _)| find_preset_node(name, "look-at"))
+            .collect::<Option<Vec<_>>>()
+        {
+            // The model faces z
+            let forward = model.local_axis(nodes[2], Vec3::Z);
+            let joints = joints
+                .iter()
+                .zip(nodes)
+                .map(|((_, weight, limit), node)| LookAtJoint::new(Some(node), *weight, limit.to_radians()))
+                .collect();
+            let mut look_at = LookAt::new(joints, forward, data.camera.position);
+            look_at.follow_camera = true;
+            look_at.enabled = false;
+            data.look_ats.push(look_at);
+        }
+        data.foot_ik_legs = ["Left", "Right"]
+            .iter()
+            .filter_map(|side| {
+                let node = |name: &str| find_preset_node(&format!("{}{}", side, name), "foot IK leg");
+                Some([node("UpLeg")?, node("Leg")?, node("Foot")?])
+            })
+            .collect();
+        let states_path = model_path.with_extension("states.json");
+        if states_path.exists() {
+            match Animator::load(&states_path, &model) {
+                Ok(animator) => data.animator = Some(animator),
+                Err(err) => warn!("Could not load the state machine: {:?}", err),
+            }
+        }
 
         let basic_object_renderer = BasicObjectRenderer::new(&device, &camera_bind_group_layout, &config, &mut data);
         Self {
@@ -379,7 +591,9 @@ impl State {
             data,
             render_pipeline,
             render_pipeline_dq,
-            woman_model,
+            render_pipeline_static,
+            skinning_pass,
+            model,
             depth_texture,
             egui_renderer,
             camera_mat_buffer,
@@ -425,9 +639,18 @@ impl State {
             }
             WindowEvent::CursorMoved { position, .. } => {
                 self.data.save_mouse_pos(position);
+                if let Some((chain, handle)) = self.data.dragged_ik_handle {
+                    self.drag_ik_handle(chain, handle);
+                }
+                true
+            }
+            WindowEvent::MouseInput { button, state, .. } if *button == MouseButton::Left => {
+                self.data.dragged_ik_handle = match state {
+                    ElementState::Pressed => self.ik_handle_under_mouse(),
+                    ElementState::Released => None,
+                };
                 true
             }
-            WindowEvent::MouseInput { button, .. } if *button == MouseButton::Left => true,
             WindowEvent::MouseInput { button, .. } if *button == MouseButton::Right => {
                 self.data.mouse_locked = !self.data.mouse_locked;
                 true
@@ -436,6 +659,63 @@ impl State {
         }
     }
 
+    fn screen_size(&self) -> Vec2 {
+        Vec2::new(self.size.width as f32, self.size.height as f32)
+    }
+
+    fn mouse_position(&self) -> Vec2 {
+        Vec2::new(self.data.mouse_pos.x as f32, self.data.mouse_pos.y as f32)
+    }
+
+    /// Gizmo of an enabled chain the closest to the mouse
+    fn ik_handle_under_mouse(&self) -> Option<(usize, IkHandle)> {
+        const GIZMO_RADIUS: f32 = 20.0;
+        let mouse = self.mouse_position();
+        let mut handles = Vec::new();
+        for (i, chain) in self
+            .data
+            .ik_chains
+            .iter()
+            .enumerate()
+            .filter(|(_, chain)| chain.enabled)
+        {
+            handles.push((i, IkHandle::Target, chain.target));
+            if let Some(pole) = chain.pole.filter(|_| chain.solver == IkSolver::TwoBone) {
+                handles.push((i, IkHandle::Pole, pole));
+            }
+        }
+        handles
+            .into_iter()
+            .filter_map(|(i, handle, point)| {
+                let position = self.data.camera.project(point, self.screen_size())?;
+                Some(((i, handle), position.distance(mouse)))
+            })
+            .filter(|(_, distance)| *distance < GIZMO_RADIUS)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(handle, _)| handle)
+    }
+
+    /// Move a point of a chain under the mouse, in the plane facing the camera
+    fn drag_ik_handle(&mut self, chain: usize, handle: IkHandle) {
+        let (origin, direction) = self.data.camera.screen_ray(self.mouse_position(), self.screen_size());
+        let normal = self.data.camera.view_direction;
+        let facing = direction.dot(normal);
+        let Some(chain) = self.data.ik_chains.get_mut(chain) else {
+            return;
+        };
+        let point = match handle {
+            IkHandle::Target => &mut chain.target,
+            IkHandle::Pole => match &mut chain.pole {
+                Some(pole) => pole,
+                None => return,
+            },
+        };
+        if facing.abs() > f32::EPSILON {
+            let distance = (*point - origin).dot(normal) / facing;
+            *point = origin + direction * distance;
+        }
+    }
+
     pub fn raw_input(&mut self, event: &DeviceEvent) -> bool {
         match event {
             DeviceEvent::MouseMotion { delta } => {
@@ -455,17 +735,215 @@ impl State {
         self.count_fps(dt);
         self.update_timeline(dt);
         self.data.camera.move_update();
+        self.update_look_ats(dt);
     }
 
     fn update_timeline(&mut self, dt: Duration) {
+        let selected = self.data.selected_animation;
+        self.data.play_selected_animation();
+
+        for (animation, mode) in self.data.root_motion_modes.iter().enumerate() {
+            self.model.set_root_motion_mode(animation, *mode);
+        }
+        let driving_animation = match self.data.animator.as_ref().filter(|_| self.data.use_state_machine) {
+            Some(animator) => animator.current_animation(),
+            None => Some(selected),
+        };
+        self.data.root_motion_active =
+            driving_animation.is_some_and(|animation| self.data.root_motion_modes[animation] == RootMotionMode::Apply);
+
         if self.data.pause {
+            // Scrubbing the paused timeline still crosses events, but does not move the model
+            let sweeps = self.data.playhead.advance(
+                0.0,
+                self.data.animations_duration[selected],
+                self.data.wrap_modes[selected],
+            );
+            self.play_sweeps(selected, &sweeps.all());
             return;
         }
-        
-        let speed = self.data.speed;
-        self.data.interpolation += dt.as_secs_f32() * speed;
-        if self.data.interpolation > self.woman_model.animations()[self.data.selected_animation].duration() {
-            self.data.interpolation = 0.0;
+
+        let delta = dt.as_secs_f32() * self.data.speed;
+        let blend_root_weight = self.blend_root_weight();
+        let use_state_machine = self.data.use_state_machine;
+        if let Some(animator) = self.data.animator.as_mut().filter(|_| use_state_machine) {
+            // Only the current state moves the model, the motion of the state faded out by a transition and of the
+            // other animations of a blend space is not blended in
+            for (animation, from, to) in animator.update(dt.as_secs_f32(), self.data.speed) {
+                let sweeps = Sweeps {
+                    scrubbed: None,
+                    played: vec![(from, to)],
+                };
+                self.play_sweeps(animation, &sweeps.played);
+                self.data
+                    .apply_root_motion(&self.model, animation, &sweeps, 1.0 - blend_root_weight);
+            }
+        } else {
+            self.advance_selected_animation(dt.as_secs_f32(), delta, 1.0 - blend_root_weight);
+        }
+
+        for layer in self.data.layers.iter_mut() {
+            let animation = layer.animation;
+            layer.playhead.advance(
+                delta,
+                self.data.animations_duration[animation],
+                self.data.wrap_modes[animation],
+            );
+        }
+
+        if let Some(blend) = self.data.blend_animation {
+            let sweeps = self.data.blend_playhead.advance(
+                delta,
+                self.data.animations_duration[blend],
+                self.data.wrap_modes[blend],
+            );
+            self.data
+                .apply_root_motion(&self.model, blend, &sweeps, blend_root_weight);
+        }
+    }
+
+    /// Share of the root joint taken by the blended animation
+    fn blend_root_weight(&self) -> f32 {
+        if self.data.blend_animation.is_none() {
+            return 0.0;
+        }
+        let mask = self.data.blend_mask.and_then(|mask| self.data.masks.get(mask));
+        match (mask, self.model.root_joint()) {
+            (Some(mask), Some(root)) => self.data.blend_weight * mask.weight(root),
+            _ => self.data.blend_weight,
+        }
+    }
+
+    /// Move the targets of the look-at constraints, following the camera for the ones set to
+    fn update_look_ats(&mut self, dt: Duration) {
+        let camera = self.data.camera.position;
+        for look_at in self.data.look_ats.iter_mut() {
+            if look_at.follow_camera {
+                look_at.target = camera;
+            }
+            look_at.update(dt.as_secs_f32());
+        }
+    }
+
+    /// Turn the joints of the enabled look-at constraints
+    fn solve_look_ats(&mut self, model_matrix: Mat4) {
+        for look_at in self.data.look_ats.iter_mut().filter(|look_at| look_at.enabled) {
+            look_at.angle = self.model.solve_look_at(look_at, model_matrix);
+        }
+    }
+
+    /// Plant the feet on the ground when the foot IK is enabled
+    fn place_feet(&mut self, model_matrix: Mat4) {
+        self.data.foot_contacts = if self.data.foot_ik {
+            self.model
+                .solve_foot_ik(&self.data.foot_ik_legs, &self.data.ground, model_matrix)
+        } else {
+            Vec::new()
+        };
+    }
+
+    /// Move the enabled chains toward their targets, the CCD and two bone ones one after the other then the FABRIK ones
+    /// together
+    fn solve_ik(&mut self, model_matrix: Mat4) {
+        let mut fabrik_chains = Vec::new();
+        let mut fabrik_indices = Vec::new();
+        for (i, chain) in self.data.ik_chains.iter_mut().enumerate() {
+            let (Some(root), Some(effector)) = (chain.root, chain.effector) else {
+                continue;
+            };
+            let Some(nodes) = self.model.ik_chain(root, effector).filter(|_| chain.enabled) else {
+                continue;
+            };
+            match chain.solver {
+                IkSolver::Ccd => {
+                    chain.distance = self
+                        .model
+                        .solve_ccd(&nodes, chain.target, model_matrix, &self.data.ik_settings);
+                }
+                IkSolver::Fabrik => {
+                    fabrik_chains.push((nodes, chain.target));
+                    fabrik_indices.push(i);
+                }
+                IkSolver::TwoBone => {
+                    chain.distance =
+                        self.model
+                            .solve_two_bone(&nodes, chain.target, chain.pole, chain.softness, model_matrix);
+                }
+            }
+        }
+
+        self.data.ik_debug_bones.clear();
+        if !fabrik_chains.is_empty() {
+            let solution = self
+                .model
+                .solve_fabrik(&fabrik_chains, model_matrix, &self.data.ik_settings);
+            for (i, distance) in fabrik_indices.into_iter().zip(solution.distances) {
+                self.data.ik_chains[i].distance = distance;
+            }
+            self.data.ik_debug_bones = solution.bones;
+        }
+    }
+
+    /// Advance the animation picked in the GUI and the ones it is fading from. Their root motion is weighted by their
+    /// share of the cross-fades, times `root_weight`.
+    fn advance_selected_animation(&mut self, dt: f32, delta: f32, root_weight: f32) {
+        let selected = self.data.selected_animation;
+        let duration = self.data.animations_duration[selected];
+        let fade_weight = self
+            .data
+            .cross_fade
+            .as_ref()
+            .map_or(1.0, |cross_fade| cross_fade.weight());
+        let sweeps = self
+            .data
+            .playhead
+            .advance(delta, duration, self.data.wrap_modes[selected]);
+        self.play_sweeps(selected, &sweeps.all());
+        self.data
+            .apply_root_motion(&self.model, selected, &sweeps, root_weight * fade_weight);
+        if self.data.playhead.finished {
+            self.data.pause = true;
+        }
+
+        // Each fade interrupted shares what is left by the fade after it
+        let mut from_weight = root_weight * (1.0 - fade_weight);
+        let mut from_sweeps = Vec::new();
+        let mut fade = self.data.cross_fade.as_mut();
+        while let Some(cross_fade) = fade {
+            let from = cross_fade.from;
+            let sweeps = cross_fade.from_playhead.advance(
+                delta,
+                self.data.animations_duration[from],
+                self.data.wrap_modes[from],
+            );
+            let weight = cross_fade.previous.as_ref().map_or(1.0, |previous| previous.weight());
+            from_sweeps.push((from, sweeps, from_weight * weight));
+            from_weight *= 1.0 - weight;
+            fade = cross_fade.previous.as_deref_mut();
+        }
+        if let Some(cross_fade) = &mut self.data.cross_fade {
+            if !cross_fade.update(dt) {
+                self.data.cross_fade = None;
+            }
+        }
+        for (from, sweeps, weight) in from_sweeps {
+            self.data.apply_root_motion(&self.model, from, &sweeps, weight);
+        }
+    }
+
+    /// Queue the events of the animation crossed by the swept intervals
+    fn play_sweeps(&mut self, animation: usize, sweeps: &[(f32, f32)]) {
+        let Some(animation_data) = self.model.animations().get(animation) else {
+            return;
+        };
+        for (from, to) in sweeps {
+            for event in animation_data.crossed_events(*from, *to) {
+                self.data.push_event(FiredEvent {
+                    animation,
+                    name: event.name.clone(),
+                    time: event.time,
+                });
+            }
         }
     }
 
@@ -481,10 +959,11 @@ impl State {
             .write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_mat_buffer]));
         self.window.set_cursor_visible(!self.data.mouse_locked);
 
+        let model_matrix = self.data.calculate_model_matrix();
         self.queue.write_buffer(
             &self.model_mat_buffer,
             0,
-            bytemuck::cast_slice(&self.data.calculate_model_matrix().to_cols_array_2d()),
+            bytemuck::cast_slice(&model_matrix.to_cols_array_2d()),
         );
         self.queue.write_buffer(
             &self.light_buffer,
@@ -492,19 +971,29 @@ impl State {
             bytemuck::cast_slice(&[LightBuffer::new(&self.data.light_pos, &self.data.light_color)]),
         );
 
-        let animation = {
-            if self.data.animations.len() > 0 {
-                Some(self.data.selected_animation)
-            } else {
-                None
+        if !self.model.animations().is_empty() {
+            let use_state_machine = self.data.use_state_machine;
+            let mut pose = match self.data.animator.as_ref().filter(|_| use_state_machine) {
+                Some(animator) => animator.sample_pose(&self.model),
+                None => self.data.sample_selected_animation(&self.model),
+            };
+            if let Some(blend) = self.data.blend_animation {
+                let blend_pose = self.model.sample_pose(blend, self.data.blend_playhead.time);
+                match self.data.blend_mask.and_then(|mask| self.data.masks.get(mask)) {
+                    Some(mask) => pose.blend_masked(&blend_pose, self.data.blend_weight, mask),
+                    None => pose.blend(&blend_pose, self.data.blend_weight),
+                }
             }
-        };
-        self.woman_model.render_animation(
-            self.data.interpolation,
-            animation,
-            &self.queue,
-            self.data.double_quat_joints_render,
-        );
+            for layer in self.data.layers.iter() {
+                layer.apply(&self.model, &mut pose, &self.data.masks);
+            }
+            self.model.apply_pose(&pose);
+        }
+        self.place_feet(model_matrix);
+        self.solve_look_ats(model_matrix);
+        self.solve_ik(model_matrix);
+        self.model
+            .render_animation(&self.queue, self.data.double_quat_joints_render);
 
         let output = self.surface.get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
@@ -512,6 +1001,18 @@ impl State {
             label: Some("Render Encoder"),
         });
 
+        match self.data.skinning {
+            SkinningMode::VertexShader => {}
+            SkinningMode::Compute => {
+                self.skinning_pass
+                    .dispatch(&mut encoder, &self.model, self.data.double_quat_joints_render);
+            }
+            SkinningMode::Cpu => {
+                self.model
+                    .upload_skinned_vertices(&self.queue, self.data.double_quat_joints_render);
+            }
+        }
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
@@ -535,7 +1036,10 @@ impl State {
                 timestamp_writes: None,
             });
 
-            if self.data.double_quat_joints_render {
+            let skinned = self.data.skinning != SkinningMode::VertexShader;
+            if skinned {
+                render_pass.set_pipeline(&self.render_pipeline_static);
+            } else if self.data.double_quat_joints_render {
                 render_pass.set_pipeline(&self.render_pipeline_dq);
             } else {
                 render_pass.set_pipeline(&self.render_pipeline);
@@ -543,7 +1047,11 @@ impl State {
             render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
             render_pass.set_bind_group(2, &self.light_bind_group, &[]);
             render_pass.set_vertex_buffer(1, self.model_mat_buffer.slice(..));
-            self.woman_model.draw(&mut render_pass);
+            if skinned {
+                self.model.draw_skinned(&mut render_pass);
+            } else {
+                self.model.draw(&mut render_pass);
+            }
 
             self.basic_object_renderer
                 .render(&mut render_pass, &self.camera_bind_group, &mut self.data, &self.device);
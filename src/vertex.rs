@@ -1,13 +1,103 @@
 use egui_wgpu::wgpu;
 
+/// Joints affecting a vertex at most, the shaders of `InfluenceLayout::Eight` read them as two vec4
+pub const MAX_JOINT_INFLUENCES: usize = 8;
+
+/// Layout of the skinned vertices on the GPU, picked from the joint influences the model uses
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InfluenceLayout {
+    /// `CompactVertex`, 4 joints per vertex
+    Four,
+    /// `Vertex`, 8 joints per vertex
+    Eight,
+}
+
+impl InfluenceLayout {
+    pub fn for_influences(influences: usize) -> Self {
+        if influences <= 4 {
+            InfluenceLayout::Four
+        } else {
+            InfluenceLayout::Eight
+        }
+    }
+
+    pub fn influences(self) -> usize {
+        match self {
+            InfluenceLayout::Four => 4,
+            InfluenceLayout::Eight => 8,
+        }
+    }
+
+    pub fn vertex_desc(self) -> wgpu::VertexBufferLayout<'static> {
+        match self {
+            InfluenceLayout::Four => CompactVertex::desc(),
+            InfluenceLayout::Eight => Vertex::desc(),
+        }
+    }
+
+    /// Influences struct and accessors of the layout, to prepend to the skinning shaders
+    pub fn wgsl(self) -> &'static str {
+        match self {
+            InfluenceLayout::Four => include_str!("influences_4.wgsl"),
+            InfluenceLayout::Eight => include_str!("influences_8.wgsl"),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    pub affected_joints: [u32; MAX_JOINT_INFLUENCES],
+    pub joints_weights: [f32; MAX_JOINT_INFLUENCES],
+    /// First morph delta, first morph weight and number of morph targets
+    pub morph_targets: [u32; 3],
+}
+
+/// Vertex uploaded for the models with 4 joint influences at most
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CompactVertex {
     pub position: [f32; 3],
     pub normal: [f32; 3],
     pub uv: [f32; 2],
     pub affected_joints: [u32; 4],
     pub joints_weights: [f32; 4],
+    pub morph_targets: [u32; 3],
+}
+
+impl From<&Vertex> for CompactVertex {
+    fn from(vertex: &Vertex) -> Self {
+        let [affected_joints @ .., _, _, _, _] = vertex.affected_joints;
+        let [joints_weights @ .., _, _, _, _] = vertex.joints_weights;
+        Self {
+            position: vertex.position,
+            normal: vertex.normal,
+            uv: vertex.uv,
+            affected_joints,
+            joints_weights,
+            morph_targets: vertex.morph_targets,
+        }
+    }
+}
+
+/// Vertex written by the compute skinning pass, in model space
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinnedVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+/// Displacement of a vertex for one morph target
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MorphDelta {
+    pub position: [f32; 4],
+    pub normal: [f32; 4],
 }
 
 impl Vertex {
@@ -15,6 +105,65 @@ impl Vertex {
         wgpu::VertexBufferLayout {
             array_stride: size_of::<Vertex>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: (size_of::<[f32; 3]>() * 2) as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: (size_of::<[f32; 3]>() * 2 + size_of::<[f32; 2]>()) as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: (size_of::<[f32; 3]>() * 2 + size_of::<[f32; 2]>() + size_of::<[u32; 4]>())
+                        as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Uint32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: (size_of::<[f32; 3]>() * 2 + size_of::<[f32; 2]>() + size_of::<[u32; 8]>())
+                        as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: (size_of::<[f32; 3]>() * 2
+                        + size_of::<[f32; 2]>()
+                        + size_of::<[u32; 8]>()
+                        + size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: (size_of::<[f32; 3]>() * 2
+                        + size_of::<[f32; 2]>()
+                        + size_of::<[u32; 8]>()
+                        + size_of::<[f32; 8]>()) as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Uint32x3,
+                },
+            ],
+        }
+    }
+}
+
+impl CompactVertex {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<CompactVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
             attributes: &[
                 wgpu::VertexAttribute {
                     offset: 0,
@@ -42,6 +191,40 @@ impl Vertex {
                     shader_location: 4,
                     format: wgpu::VertexFormat::Float32x4,
                 },
+                wgpu::VertexAttribute {
+                    offset: (size_of::<[f32; 3]>() * 2
+                        + size_of::<[f32; 2]>()
+                        + size_of::<[u32; 4]>()
+                        + size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Uint32x3,
+                },
+            ],
+        }
+    }
+}
+
+impl SkinnedVertex {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<SkinnedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: (size_of::<[f32; 3]>() * 2) as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
             ],
         }
     }